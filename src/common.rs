@@ -6,6 +6,20 @@ pub struct Color {
     pub blue: u8,
 }
 
+/// A colour in the form that it was given in the input, this is only turned into a 24 bit Color when it is
+/// written out using a Palette so that the output can refer to the colour the same way the input did.
+///
+/// The default colour is not a variant as it is represented by the colour being None e.g. SGR 39.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum AnsiColor {
+    /// One of the 16 base colours as set by SGR 30-37, 40-47, 90-97 and 100-107, 8-15 are the bright varients
+    Named(u8),
+    /// An index into the 256 colour palette e.g. `38;5;n`
+    Indexed(u8),
+    /// A 24 bit colour e.g. `38;2;r;g;b`
+    Rgb(Color),
+}
+
 /// An element of a ANSI Complient string containing either a section of text or an escape sequence
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum TextElement<T> {
@@ -26,6 +40,7 @@ pub struct Text<T> {
     pub text: Vec<TextElement<T>>,
 }
 
+#[allow(clippy::needless_return, clippy::clone_on_copy)]
 impl Color {
     pub fn red(&self) -> u8 {
         return self.red.clone();
@@ -40,7 +55,7 @@ impl Color {
     }
 
     /// This converts the Color given into a bright color varient.
-    pub fn make_bright(mut color: Color) -> Color {
+    pub const fn make_bright(mut color: Color) -> Color {
         if color.green == 0 && color.blue == 0 && color.red == 0 {
             color.green = 128;
             color.blue = 128;
@@ -61,7 +76,7 @@ impl Color {
 }
 
 /// Returns a Color that represents black
-pub fn black() -> Color {
+pub const fn black() -> Color {
     Color {
         red: 0,
         green: 0,
@@ -70,7 +85,7 @@ pub fn black() -> Color {
 }
 
 /// Returns a Color that represents red
-pub fn red() -> Color {
+pub const fn red() -> Color {
     Color {
         red: 128,
         green: 0,
@@ -79,7 +94,7 @@ pub fn red() -> Color {
 }
 
 /// Returns a Color that represents green
-pub fn green() -> Color {
+pub const fn green() -> Color {
     Color {
         red: 0,
         green: 128,
@@ -88,7 +103,7 @@ pub fn green() -> Color {
 }
 
 /// Returns a Color that represents yellow
-pub fn yellow() -> Color {
+pub const fn yellow() -> Color {
     Color {
         red: 128,
         green: 128,
@@ -97,7 +112,7 @@ pub fn yellow() -> Color {
 }
 
 /// Returns a Color that represents blue
-pub fn blue() -> Color {
+pub const fn blue() -> Color {
     Color {
         red: 0,
         green: 0,
@@ -106,7 +121,7 @@ pub fn blue() -> Color {
 }
 
 /// Returns a Color that represents magenta
-pub fn magenta() -> Color {
+pub const fn magenta() -> Color {
     Color {
         red: 128,
         green: 0,
@@ -115,7 +130,7 @@ pub fn magenta() -> Color {
 }
 
 /// Returns a Color that represents cyan
-pub fn cyan() -> Color {
+pub const fn cyan() -> Color {
     Color {
         red: 0,
         green: 128,
@@ -124,7 +139,7 @@ pub fn cyan() -> Color {
 }
 
 /// Returns a Color that represents white
-pub fn white() -> Color {
+pub const fn white() -> Color {
     Color {
         red: 192,
         green: 192,
@@ -132,6 +147,55 @@ pub fn white() -> Color {
     }
 }
 
+/// The levels that each channel of the 6x6x6 colour cube in the xterm palette can take
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The 256 colour palette used by xterm for the 8 bit colour codes e.g. `38;5;n`.
+///
+/// - 0-7 are the standard colours and 8-15 are their bright varients using xterm's default system colours
+/// - 16-231 are a 6x6x6 colour cube where the index is `16 + 36 * red + 6 * green + blue`
+/// - 232-255 are a greyscale ramp from 8 to 238 in steps of 10
+pub const XTERM_PALETTE: [Color; 256] = xterm_palette();
+
+/// The 16 system colours that xterm uses by default
+const XTERM_SYSTEM_COLORS: [u32; 16] = [
+    0x000000, 0xcd0000, 0x00cd00, 0xcdcd00, 0x0000ee, 0xcd00cd, 0x00cdcd, 0xe5e5e5, 0x7f7f7f,
+    0xff0000, 0x00ff00, 0xffff00, 0x5c5cff, 0xff00ff, 0x00ffff, 0xffffff,
+];
+
+const fn xterm_palette() -> [Color; 256] {
+    let mut palette = [black(); 256];
+    let mut index = 0;
+    while index < 16 {
+        let hex = XTERM_SYSTEM_COLORS[index];
+        palette[index] = Color {
+            red: (hex >> 16) as u8,
+            green: (hex >> 8) as u8,
+            blue: hex as u8,
+        };
+        index += 1;
+    }
+    while index < 232 {
+        let cube = index - 16;
+        palette[index] = Color {
+            red: CUBE_LEVELS[cube / 36],
+            green: CUBE_LEVELS[cube / 6 % 6],
+            blue: CUBE_LEVELS[cube % 6],
+        };
+        index += 1;
+    }
+    while index < 256 {
+        let level = 8 + (index - 232) as u8 * 10;
+        palette[index] = Color {
+            red: level,
+            green: level,
+            blue: level,
+        };
+        index += 1;
+    }
+    palette
+}
+
 pub mod test {
 
     #[test]
@@ -274,4 +338,36 @@ pub mod test {
             assert_eq!(color.blue(), expected_result.blue());
         }
     }
+
+    #[cfg(test)]
+    fn rgb(red: u8, green: u8, blue: u8) -> super::Color {
+        super::Color { red, green, blue }
+    }
+
+    #[test]
+    fn color_xterm_palette() {
+        let tests = [
+            (0, rgb(0x00, 0x00, 0x00)),
+            (1, rgb(0xcd, 0x00, 0x00)),
+            (4, rgb(0x00, 0x00, 0xee)),
+            (7, rgb(0xe5, 0xe5, 0xe5)),
+            (8, rgb(0x7f, 0x7f, 0x7f)),
+            (12, rgb(0x5c, 0x5c, 0xff)),
+            (15, rgb(0xff, 0xff, 0xff)),
+            (16, rgb(0x00, 0x00, 0x00)),
+            (21, rgb(0x00, 0x00, 0xff)),
+            (46, rgb(0x00, 0xff, 0x00)),
+            (67, rgb(0x5f, 0x87, 0xaf)),
+            (130, rgb(0xaf, 0x5f, 0x00)),
+            (196, rgb(0xff, 0x00, 0x00)),
+            (208, rgb(0xff, 0x87, 0x00)),
+            (231, rgb(0xff, 0xff, 0xff)),
+            (232, rgb(0x08, 0x08, 0x08)),
+            (244, rgb(0x80, 0x80, 0x80)),
+            (255, rgb(0xee, 0xee, 0xee)),
+        ];
+        for (index, expected_result) in tests {
+            assert_eq!(super::XTERM_PALETTE[index], expected_result, "{}", index);
+        }
+    }
 }
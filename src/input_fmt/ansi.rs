@@ -1,26 +1,23 @@
 use crate::common;
+use crate::input_fmt::literal::LiteralEscapes;
+use clap::ValueEnum;
 use std::str::Chars;
 /// This is an alias for the ASCII Escape character
-static ESC: char = 0x1B as char;
+static ESC: char = '\x1b';
+/// This is an alias for the ASCII Bell character which can be used to end an OSC string
+static BEL: char = '\x07';
+/// This is an alias for the ASCII Cancel character which aborts any sequence in progress
+static CAN: char = '\x18';
+/// This is an alias for the ASCII Substitute character which aborts any sequence in progress
+static SUB: char = '\x1a';
+/// The most characters an OSC, DCS or other string may hold, a longer string is assumed to be unterminated and is
+/// given back as text
+static MAX_STRING_LENGTH: usize = 1 << 16;
 
-pub type Color = common::Color;
+pub type Color = common::AnsiColor;
 
 impl Color {
-    pub fn from_index(index: u8) -> Option<Color> {
-        return match index {
-            0 => Some(common::black()),
-            1 => Some(common::red()),
-            2 => Some(common::green()),
-            3 => Some(common::yellow()),
-            4 => Some(common::blue()),
-            5 => Some(common::magenta()),
-            6 => Some(common::cyan()),
-            7 => Some(common::white()),
-            _ => None,
-        };
-    }
-
-    /// Converts the args used as part of SelectGraphicsRendition into a 24bit Color variable
+    /// Converts the args used as part of SelectGraphicsRendition into a Color
     ///
     /// The function expects that the user is passing in arguments in the on of the following structure
     ///
@@ -30,25 +27,36 @@ impl Color {
     /// if arguments are not provided it will assume a value or 0 for these arguments it will only
     /// return 0 if the color mode provided as the first argument is not present or recognised
     pub fn from_args(args: &mut Vec<u8>) -> Option<Color> {
-        return match args.pop() {
+        match args.pop() {
             Some(arg) => match arg {
-                2 => Some(Color {
+                2 => Some(Color::Rgb(common::Color {
                     red: args.pop().unwrap_or(0),
                     green: args.pop().unwrap_or(0),
                     blue: args.pop().unwrap_or(0),
-                }),
-                5 => match args.pop() {
-                    Some(color) => Some(Color {
-                        red: (color >> 5) * 32,
-                        green: ((color & 28) >> 2) * 32,
-                        blue: (color & 3) * 32,
-                    }),
-                    None => Some(common::black()),
-                },
+                })),
+                5 => Some(Color::Indexed(args.pop().unwrap_or(0))),
                 _ => None,
             },
             None => None,
+        }
+    }
+
+    /// Converts the colon separated sub-parameters of a colour SGR (ITU T.416) into a Color.
+    ///
+    /// `args` holds the values that followed the `38`, `48` or `58` inside of the same group i.e. for
+    /// `38:2::255:128:0` it would be `[2, 0, 255, 128, 0]`. The colour space ID that comes before the red value is
+    /// optional so it is only expected when all of the other values are present.
+    pub fn from_sub_args(args: &[u8]) -> Option<Color> {
+        let (mode, values) = args.split_first()?;
+        let values = match mode {
+            2 if values.len() > 3 => &values[1..],
+            _ => values,
         };
+        let mut stack: Vec<u8> = std::iter::once(*mode)
+            .chain(values.iter().copied())
+            .collect();
+        stack.reverse();
+        Color::from_args(&mut stack)
     }
 }
 
@@ -94,22 +102,41 @@ pub enum SelectGraphicRendition {
     Superscript,
     Subscript,
     NethirSuperOrSubScript,
+    CurlyUnderline,
+    DottedUnderline,
+    DashedUnderline,
 }
 
 impl SelectGraphicRendition {
     /// This will parse the args of the SelectGraphics rendition into the concreate values used internally
     /// This is expected to that either a number to represent a particular graphics change or non will be
     /// proivded at which point it will revert to the default settings
-    pub fn from(args: &mut Vec<u8>) -> Vec<SelectGraphicRendition> {
-        args.reverse();
+    ///
+    /// Each arg is a group of the colon separated sub-parameters given between two `;`, the first value of
+    /// the group selects the graphics change and the rest are only used by the colours and underline styles.
+    pub fn from(args: &mut Vec<Vec<u16>>) -> Vec<SelectGraphicRendition> {
+        let mut args: Vec<Vec<u16>> = args.drain(..).rev().collect();
         let mut graphics: Vec<SelectGraphicRendition> = Vec::new();
-        while let Some(arg) = args.pop() {
+        while let Some(group) = args.pop() {
+            let (arg, sub_args) = match group.split_first() {
+                // there are no graphics changes above 255 so those are left unrecognised
+                Some((arg, sub_args)) => (u8::try_from(*arg).unwrap_or(u8::MAX), sub_args),
+                None => (0, &[] as &[u16]),
+            };
             let sgr_opt = match arg {
                 0 => Some(SelectGraphicRendition::Normal),
                 1 => Some(SelectGraphicRendition::Bold),
                 2 => Some(SelectGraphicRendition::Faint),
                 3 => Some(SelectGraphicRendition::Italic),
-                4 => Some(SelectGraphicRendition::Underline),
+                4 => match sub_args.first() {
+                    None | Some(1) => Some(SelectGraphicRendition::Underline),
+                    Some(0) => Some(SelectGraphicRendition::NotUnderlined),
+                    Some(2) => Some(SelectGraphicRendition::DoublyUnderlined),
+                    Some(3) => Some(SelectGraphicRendition::CurlyUnderline),
+                    Some(4) => Some(SelectGraphicRendition::DottedUnderline),
+                    Some(5) => Some(SelectGraphicRendition::DashedUnderline),
+                    _ => None,
+                },
                 5 => Some(SelectGraphicRendition::SlowBlink),
                 6 => Some(SelectGraphicRendition::RapidBlink),
                 7 => Some(SelectGraphicRendition::Invert),
@@ -125,19 +152,17 @@ impl SelectGraphicRendition {
                 27 => Some(SelectGraphicRendition::NotReveresed),
                 28 => Some(SelectGraphicRendition::Reveal),
                 29 => Some(SelectGraphicRendition::NotCrossedOut),
-                30..=37 => Some(SelectGraphicRendition::ForgroundColor(Color::from_index(
+                30..=37 => Some(SelectGraphicRendition::ForgroundColor(Some(Color::Named(
                     arg - 30,
-                ))),
-                38 => Some(SelectGraphicRendition::ForgroundColor(Color::from_args(
-                    args,
-                ))),
+                )))),
+                38 => SelectGraphicRendition::color(sub_args, &mut args)
+                    .map(SelectGraphicRendition::ForgroundColor),
                 39 => Some(SelectGraphicRendition::ForgroundColor(None)),
-                40..=47 => Some(SelectGraphicRendition::BackgroundColor(Color::from_index(
+                40..=47 => Some(SelectGraphicRendition::BackgroundColor(Some(Color::Named(
                     arg - 40,
-                ))),
-                48 => Some(SelectGraphicRendition::BackgroundColor(Color::from_args(
-                    args,
-                ))),
+                )))),
+                48 => SelectGraphicRendition::color(sub_args, &mut args)
+                    .map(SelectGraphicRendition::BackgroundColor),
                 49 => Some(SelectGraphicRendition::BackgroundColor(None)),
                 50 => Some(SelectGraphicRendition::DisableProportionalSpacing),
                 51 => Some(SelectGraphicRendition::Framed),
@@ -145,9 +170,8 @@ impl SelectGraphicRendition {
                 53 => Some(SelectGraphicRendition::Overlined),
                 54 => Some(SelectGraphicRendition::NeitherFramedNorEncircled),
                 55 => Some(SelectGraphicRendition::NotOverlined),
-                58 => Some(SelectGraphicRendition::SetUnderlineColor(Color::from_args(
-                    args,
-                ))),
+                58 => SelectGraphicRendition::color(sub_args, &mut args)
+                    .map(SelectGraphicRendition::SetUnderlineColor),
                 59 => Some(SelectGraphicRendition::SetUnderlineColor(None)),
                 60 => Some(SelectGraphicRendition::IdeogramUnderline),
                 61 => Some(SelectGraphicRendition::IdeogramDoubleUnderline),
@@ -158,58 +182,175 @@ impl SelectGraphicRendition {
                 73 => Some(SelectGraphicRendition::Superscript),
                 74 => Some(SelectGraphicRendition::Subscript),
                 75 => Some(SelectGraphicRendition::NethirSuperOrSubScript),
-                90..=97 => match Color::from_index(arg - 90) {
-                    Some(c) => Some(SelectGraphicRendition::ForgroundColor(Some(
-                        Color::make_bright(c),
-                    ))),
-                    None => Some(SelectGraphicRendition::ForgroundColor(None)),
-                },
-                100..=107 => match Color::from_index(arg - 100) {
-                    Some(c) => Some(SelectGraphicRendition::BackgroundColor(Some(
-                        Color::make_bright(c),
-                    ))),
-                    None => Some(SelectGraphicRendition::BackgroundColor(None)),
-                },
+                90..=97 => Some(SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    arg - 90 + 8,
+                )))),
+                100..=107 => Some(SelectGraphicRendition::BackgroundColor(Some(Color::Named(
+                    arg - 100 + 8,
+                )))),
                 _ => None,
             };
             if let Some(sgr) = sgr_opt {
                 graphics.push(sgr)
             }
         }
-        return graphics;
+        graphics
+    }
+
+    /// Reads the colour for a `38`, `48` or `58`, in the colon form everything is in the sub-parameters
+    /// otherwise the mode and its values are taken from the groups that follow.
+    ///
+    /// An index or channel that doesn't fit in a u8 makes the whole colour invalid so None is returned and the
+    /// graphics change is ignored, an unrecognised mode still resets the colour like it always has.
+    fn color(sub_args: &[u16], args: &mut Vec<Vec<u16>>) -> Option<Option<Color>> {
+        if !sub_args.is_empty() {
+            let sub_args: Vec<u8> = sub_args
+                .iter()
+                .map(|arg| u8::try_from(*arg).ok())
+                .collect::<Option<Vec<u8>>>()?;
+            return Some(Color::from_sub_args(&sub_args));
+        }
+        let len = match args.last().and_then(|group| group.first()) {
+            Some(2) => 4,
+            Some(5) => 2,
+            _ => 1,
+        };
+        let mut values: Vec<u16> = Vec::new();
+        for _ in 0..len {
+            match args.pop() {
+                Some(group) => values.push(group.first().copied().unwrap_or(0)),
+                None => break,
+            }
+        }
+        let mut values: Vec<u8> = values
+            .into_iter()
+            .rev()
+            .map(|value| u8::try_from(value).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(Color::from_args(&mut values))
     }
 }
 
 /// This is the list of valid control sequences that are valid as part of the FeEscape Sequence
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum ControlSequence {
-    CursorUp(u8),
-    CursorDown(u8),
-    CursorForward(u8),
-    CursorBack(u8),
-    CursorNextLine(u8),
-    CursorPreviousLine(u8),
-    CursorHorizontalAbsolute(u8),
-    CursorPosition(u8, u8),
-    EraseInDisplay(u8),
-    EraseInLine(u8),
-    ScrollUp(u8),
-    ScrollDown(u8),
-    HorizonalVerticalPosition(u8, u8),
+    CursorUp(u16),
+    CursorDown(u16),
+    CursorForward(u16),
+    CursorBack(u16),
+    CursorNextLine(u16),
+    CursorPreviousLine(u16),
+    CursorHorizontalAbsolute(u16),
+    CursorPosition(u16, u16),
+    EraseInDisplay(u16),
+    EraseInLine(u16),
+    ScrollUp(u16),
+    ScrollDown(u16),
+    HorizonalVerticalPosition(u16, u16),
+    VerticalPositionAbsolute(u16),
+    InsertCharacters(u16),
+    DeleteCharacters(u16),
+    EraseCharacters(u16),
+    InsertLines(u16),
+    DeleteLines(u16),
+    /// DECSTBM sets the top and bottom lines of the scrolling region, a 0 means the default for that edge
+    SetScrollingRegion(u16, u16),
     SelectGraphicalRendition(Vec<SelectGraphicRendition>),
     AUXPortOn,
     AUXPortOff,
     DeviceStatusReport,
     SaveCursorPosistion,
     RestoreCursorPosistion,
-    VT220Cursor,
-    HideCursor,
-    EnableReportingFocus,
-    DisableReportingFocus,
-    EnableAltScreenBuf,
-    DisableAltScreenBuf,
-    BracketPasteMode,
-    NoBracketPasteMode,
+    SetMode(Modes),
+    ResetMode(Modes),
+}
+
+/// The modes that are given to a Set Mode (`h`) or Reset Mode (`l`) sequence
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
+pub struct Modes {
+    /// The marker given before the modes (`<`, `=`, `>` or `?`), a `?` means the modes are DEC private modes
+    pub private_marker: Option<char>,
+    pub modes: Vec<u16>,
+}
+
+impl Modes {
+    /// Returns the modes that we recognise as DEC private modes, this is always empty unless the modes were
+    /// given with the `?` marker.
+    pub fn dec_private_modes(&self) -> Vec<DecPrivateMode> {
+        if self.private_marker != Some('?') {
+            return Vec::new();
+        }
+        self.modes
+            .iter()
+            .filter_map(|mode| DecPrivateMode::from(*mode))
+            .collect()
+    }
+}
+
+/// The DEC private modes that are commonly set by programs, these are all given with the `?` marker
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum DecPrivateMode {
+    ApplicationCursorKeys,
+    Columns132,
+    SmoothScroll,
+    ReverseVideo,
+    Origin,
+    AutoWrap,
+    AutoRepeat,
+    X10Mouse,
+    BlinkingCursor,
+    ShowCursor,
+    AltScreen,
+    NormalMouseTracking,
+    HighlightMouseTracking,
+    ButtonEventMouseTracking,
+    AnyEventMouseTracking,
+    FocusReporting,
+    Utf8Mouse,
+    SgrMouse,
+    AlternateScroll,
+    UrxvtMouse,
+    SgrPixelMouse,
+    AltScreenClear,
+    SaveCursor,
+    AltScreenSaveCursor,
+    BracketedPaste,
+    SynchronizedOutput,
+}
+
+impl DecPrivateMode {
+    /// Returns the mode with the number given or None if it is not one we know of
+    pub fn from(mode: u16) -> Option<DecPrivateMode> {
+        match mode {
+            1 => Some(DecPrivateMode::ApplicationCursorKeys),
+            3 => Some(DecPrivateMode::Columns132),
+            4 => Some(DecPrivateMode::SmoothScroll),
+            5 => Some(DecPrivateMode::ReverseVideo),
+            6 => Some(DecPrivateMode::Origin),
+            7 => Some(DecPrivateMode::AutoWrap),
+            8 => Some(DecPrivateMode::AutoRepeat),
+            9 => Some(DecPrivateMode::X10Mouse),
+            12 => Some(DecPrivateMode::BlinkingCursor),
+            25 => Some(DecPrivateMode::ShowCursor),
+            47 => Some(DecPrivateMode::AltScreen),
+            1000 => Some(DecPrivateMode::NormalMouseTracking),
+            1001 => Some(DecPrivateMode::HighlightMouseTracking),
+            1002 => Some(DecPrivateMode::ButtonEventMouseTracking),
+            1003 => Some(DecPrivateMode::AnyEventMouseTracking),
+            1004 => Some(DecPrivateMode::FocusReporting),
+            1005 => Some(DecPrivateMode::Utf8Mouse),
+            1006 => Some(DecPrivateMode::SgrMouse),
+            1007 => Some(DecPrivateMode::AlternateScroll),
+            1015 => Some(DecPrivateMode::UrxvtMouse),
+            1016 => Some(DecPrivateMode::SgrPixelMouse),
+            1047 => Some(DecPrivateMode::AltScreenClear),
+            1048 => Some(DecPrivateMode::SaveCursor),
+            1049 => Some(DecPrivateMode::AltScreenSaveCursor),
+            2004 => Some(DecPrivateMode::BracketedPaste),
+            2026 => Some(DecPrivateMode::SynchronizedOutput),
+            _ => None,
+        }
+    }
 }
 
 impl ControlSequence {
@@ -221,18 +362,48 @@ impl ControlSequence {
     /// args = [int],{[";"],[int]}
     /// ```
     ///
-    pub fn get_args(text: &mut String) -> Vec<u8> {
-        let mut args: Vec<u8> = Vec::new();
+    /// Any sub-parameters given after a `:` are dropped, use get_arg_groups to keep them. An argument that is
+    /// too large to fit is clamped to u16::MAX.
+    pub fn get_args(text: &mut String) -> Vec<u16> {
+        ControlSequence::get_arg_groups(text)
+            .iter()
+            .map(|group| group.first().copied().unwrap_or(0))
+            .collect()
+    }
+
+    /// This is the same as get_args however each argument is kept together with its colon separated
+    /// sub-parameters.
+    ///
+    /// ```ebnf
+    /// group = [int],{":",[int]}
+    /// args = group,{";",group}
+    /// ```
+    pub fn get_arg_groups(text: &mut String) -> Vec<Vec<u16>> {
+        let mut args: Vec<Vec<u16>> = Vec::new();
         if let Some(c) = text.pop() {
             if c != ';' {
                 text.push(c);
             }
         }
-        for arg_str in text.split(';') {
-            args.push(arg_str.parse::<u8>().unwrap_or(0))
+        for group_str in text.split(';') {
+            args.push(
+                group_str
+                    .split(':')
+                    .map(ControlSequence::parse_arg)
+                    .collect(),
+            )
+        }
+        args
+    }
+
+    fn parse_arg(arg_str: &str) -> u16 {
+        if arg_str.is_empty() || !arg_str.bytes().all(|b| b.is_ascii_digit()) {
+            return 0;
         }
-        return args;
+        // the only way a string of digits can fail to parse is if it is too large
+        arg_str.parse::<u16>().unwrap_or(u16::MAX)
     }
+
     /// This will parse the text in the form described by the ebnf below into its internal ControlSequence representation if it is possible.
     ///
     /// ```ebf
@@ -242,149 +413,163 @@ impl ControlSequence {
     /// command_identifier = 'm'| 'A' | 'B' | 'C' | 'D' | 'E' | 'F' | 'G' | 'H' | 'J' | 'K' | 'S' | 'T' | 'f' | 'i' | 'n' | 's' | 'u'
     /// control_sequence = args, command_identifier
     /// ```
+    ///
+    /// Any character in the range 0x40-0x7E will end the sequence, if it is not one of the command identifiers
+    /// listed above the sequence is consumed and None is returned.
     pub fn from(chars: &mut Chars) -> Option<ControlSequence> {
-        let mut text_buffer = String::new();
-        while let Some(c) = chars.next() {
-            match c {
-                'm' => {
-                    let mut args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::SelectGraphicalRendition(
-                        SelectGraphicRendition::from(&mut args),
-                    ));
-                }
-                'A' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::CursorUp(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'B' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::CursorDown(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'C' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::CursorForward(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'D' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::CursorBack(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'E' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::CursorNextLine(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'F' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::CursorPreviousLine(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'G' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::CursorHorizontalAbsolute(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'H' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::CursorPosition(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                        args.get(1).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'J' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::EraseInDisplay(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'K' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::EraseInLine(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'S' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::ScrollUp(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'T' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::ScrollDown(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'f' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return Some(ControlSequence::HorizonalVerticalPosition(
-                        args.get(0).unwrap_or(&(1 as u8)).clone(),
-                        args.get(1).unwrap_or(&(1 as u8)).clone(),
-                    ));
-                }
-                'i' => {
-                    let args = ControlSequence::get_args(&mut text_buffer);
-                    return match args.get(0) {
-                        Some(arg) => match arg {
-                            5 => Some(ControlSequence::AUXPortOn),
-                            4 => Some(ControlSequence::AUXPortOff),
-                            _ => None,
-                        },
-                        None => None,
-                    };
-                }
-                'h' => {
-                    return match text_buffer.as_str() {
-                        "?25" => Some(ControlSequence::VT220Cursor),
-                        "?1004" => Some(ControlSequence::EnableReportingFocus),
-                        "?1049" => Some(ControlSequence::EnableAltScreenBuf),
-                        "?2004" => Some(ControlSequence::BracketPasteMode),
-                        _ => None,
-                    }
-                }
-                'l' => {
-                    return match text_buffer.as_str() {
-                        "?25" => Some(ControlSequence::HideCursor),
-                        "?1004" => Some(ControlSequence::DisableReportingFocus),
-                        "?1049" => Some(ControlSequence::DisableAltScreenBuf),
-                        "?2004" => Some(ControlSequence::NoBracketPasteMode),
-                        _ => None,
-                    }
-                }
-                'n' => return Some(ControlSequence::DeviceStatusReport),
-                's' => return Some(ControlSequence::SaveCursorPosistion),
-                'u' => return Some(ControlSequence::RestoreCursorPosistion),
-                _ => {
-                    text_buffer.push(c);
-                }
+        match Parser::with_state(State::CsiEntry).next_sequence(chars) {
+            Some(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(control_sequence))) => {
+                Some(control_sequence)
+            }
+            _ => None,
+        }
+    }
+
+    /// Converts a fully collected control sequence into its internal representation.
+    ///
+    /// `params` holds the parameter bytes (0x30-0x3F) `intermediates` holds the intermediate bytes (0x20-0x2F)
+    /// and `final_byte` is the character that ended the sequence. None is returned for any sequence we do not
+    /// recognise.
+    ///
+    /// A private marker (0x3C-0x3F) at the start of `params` is only understood by the set and reset mode
+    /// sequences.
+    pub fn dispatch(
+        params: &mut String,
+        intermediates: &str,
+        final_byte: char,
+    ) -> Option<ControlSequence> {
+        if !intermediates.is_empty() {
+            return None;
+        }
+        let private_marker = match params.chars().next() {
+            Some(c @ '<'..='?') => {
+                params.remove(0);
+                Some(c)
             }
+            _ => None,
+        };
+        if private_marker.is_some() && final_byte != 'h' && final_byte != 'l' {
+            return None;
+        }
+        let modes = |params: &mut String| Modes {
+            private_marker,
+            modes: ControlSequence::get_args(params),
+        };
+        let first_arg = |params: &mut String| {
+            ControlSequence::get_args(params)
+                .first()
+                .copied()
+                .unwrap_or(1)
+        };
+        let two_args = |params: &mut String| {
+            let args = ControlSequence::get_args(params);
+            (
+                args.first().copied().unwrap_or(1),
+                args.get(1).copied().unwrap_or(1),
+            )
+        };
+        match final_byte {
+            'm' => {
+                let mut args = ControlSequence::get_arg_groups(params);
+                Some(ControlSequence::SelectGraphicalRendition(
+                    SelectGraphicRendition::from(&mut args),
+                ))
+            }
+            'A' => Some(ControlSequence::CursorUp(first_arg(params))),
+            'B' => Some(ControlSequence::CursorDown(first_arg(params))),
+            'C' => Some(ControlSequence::CursorForward(first_arg(params))),
+            'D' => Some(ControlSequence::CursorBack(first_arg(params))),
+            'E' => Some(ControlSequence::CursorNextLine(first_arg(params))),
+            'F' => Some(ControlSequence::CursorPreviousLine(first_arg(params))),
+            'G' => Some(ControlSequence::CursorHorizontalAbsolute(first_arg(params))),
+            'H' => {
+                let (row, column) = two_args(params);
+                Some(ControlSequence::CursorPosition(row, column))
+            }
+            'J' => Some(ControlSequence::EraseInDisplay(first_arg(params))),
+            'K' => Some(ControlSequence::EraseInLine(first_arg(params))),
+            'S' => Some(ControlSequence::ScrollUp(first_arg(params))),
+            'T' => Some(ControlSequence::ScrollDown(first_arg(params))),
+            'f' => {
+                let (row, column) = two_args(params);
+                Some(ControlSequence::HorizonalVerticalPosition(row, column))
+            }
+            'd' => Some(ControlSequence::VerticalPositionAbsolute(first_arg(params))),
+            '@' => Some(ControlSequence::InsertCharacters(first_arg(params))),
+            'P' => Some(ControlSequence::DeleteCharacters(first_arg(params))),
+            'X' => Some(ControlSequence::EraseCharacters(first_arg(params))),
+            'L' => Some(ControlSequence::InsertLines(first_arg(params))),
+            'M' => Some(ControlSequence::DeleteLines(first_arg(params))),
+            'r' => {
+                let args = ControlSequence::get_args(params);
+                Some(ControlSequence::SetScrollingRegion(
+                    args.first().copied().unwrap_or(0),
+                    args.get(1).copied().unwrap_or(0),
+                ))
+            }
+            'i' => match ControlSequence::get_args(params).first() {
+                Some(5) => Some(ControlSequence::AUXPortOn),
+                Some(4) => Some(ControlSequence::AUXPortOff),
+                _ => None,
+            },
+            'h' => Some(ControlSequence::SetMode(modes(params))),
+            'l' => Some(ControlSequence::ResetMode(modes(params))),
+            'n' => Some(ControlSequence::DeviceStatusReport),
+            's' => Some(ControlSequence::SaveCursorPosistion),
+            'u' => Some(ControlSequence::RestoreCursorPosistion),
+            _ => None,
+        }
+    }
+}
+
+/// This is the list of Operating System Commands that we understand along with a fall back that holds on to the
+/// raw contents of any other command.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum OperatingSystemCommand {
+    /// OSC 8 this starts a hyperlink to the URI given, an empty URI ends the current hyperlink
+    Hyperlink {
+        params: String,
+        uri: String,
+    },
+    Other(String),
+}
+
+impl OperatingSystemCommand {
+    /// Parses the contents of an OSC string, that is everything between the `ESC ]` and the string terminator.
+    ///
+    /// ```ebnf
+    /// hyperlink = "8", ";", params, ";", uri
+    /// ```
+    pub fn from(payload: &str) -> OperatingSystemCommand {
+        let mut parts = payload.splitn(3, ';');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("8"), Some(params), Some(uri)) => OperatingSystemCommand::Hyperlink {
+                params: params.to_string(),
+                uri: uri.to_string(),
+            },
+            _ => OperatingSystemCommand::Other(payload.to_string()),
         }
-        return None;
     }
 }
 
 /// This is the internal reprenstation of ANSI FeEscapeSequences
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum FeEscapeSequence {
+    /// IND moves the cursor down a line scrolling if it is at the bottom
+    Index,
+    /// NEL moves the cursor to the start of the next line scrolling if it is at the bottom
+    NextLine,
+    /// RI moves the cursor up a line scrolling if it is at the top
+    ReverseIndex,
     SingleShiftTwo,
     SingleShiftThree,
-    DeviceControlString,
+    DeviceControlString(String),
     ControlSequence(ControlSequence),
-    OperatingSystemCommand,
+    OperatingSystemCommand(OperatingSystemCommand),
     StringTerminator,
-    StartOfString,
-    PrivacyMessage,
-    ApplicationProgramCommand,
+    StartOfString(String),
+    PrivacyMessage(String),
+    ApplicationProgramCommand(String),
 }
 
 impl FeEscapeSequence {
@@ -399,39 +584,626 @@ impl FeEscapeSequence {
     /// fe_escape_sequence = fe_identifier "N" | "O" | "P" | "[",control_sequence | "X" | "^" | "_" | "\\"
     /// ```
     pub fn from(chars: &mut Chars) -> Option<FeEscapeSequence> {
-        return match chars.next() {
-            Some(c) => match c {
-                'N' | 'n' => Some(FeEscapeSequence::SingleShiftTwo),
-                'O' | 'o' => Some(FeEscapeSequence::SingleShiftThree),
-                'P' | 'p' => Some(FeEscapeSequence::DeviceControlString),
-                '[' => match ControlSequence::from(chars) {
-                    Some(controlsequence) => {
-                        Some(FeEscapeSequence::ControlSequence(controlsequence))
-                    }
-                    None => None,
-                },
-                ']' => Some(FeEscapeSequence::OperatingSystemCommand),
-                'X' | 'x' => Some(FeEscapeSequence::StartOfString),
-                '^' => Some(FeEscapeSequence::PrivacyMessage),
-                '_' => Some(FeEscapeSequence::ApplicationProgramCommand),
-                '\\' => Some(FeEscapeSequence::StringTerminator),
-                _ => None,
-            },
-            None => None,
-        };
+        match EscapeSequence::from(chars) {
+            Some(EscapeSequence::Fe(fe)) => Some(fe),
+            _ => None,
+        }
+    }
+
+    /// Returns the FeEscapeSequence that is identified by the single character that follows the ESC.
+    ///
+    /// Control sequences and the string commands (DCS, OSC, SOS, PM and APC) are not included here as they need
+    /// their contents to be parsed first.
+    pub fn from_final(c: char) -> Option<FeEscapeSequence> {
+        match c {
+            'D' => Some(FeEscapeSequence::Index),
+            'E' => Some(FeEscapeSequence::NextLine),
+            'M' => Some(FeEscapeSequence::ReverseIndex),
+            'N' => Some(FeEscapeSequence::SingleShiftTwo),
+            'O' => Some(FeEscapeSequence::SingleShiftThree),
+            '\\' => Some(FeEscapeSequence::StringTerminator),
+            _ => None,
+        }
     }
 
     /// This is varient of from that returns a copy of the string given with the escape sequence removed if there was one at the start as well as the escape sequence found.
     pub fn extract_from(string: &str) -> (String, Option<FeEscapeSequence>) {
         let mut chars = string.chars();
         let esc_seq = FeEscapeSequence::from(&mut chars);
-        return (chars.as_str().to_string(), esc_seq);
+        (chars.as_str().to_string(), esc_seq)
+    }
+}
+
+/// This is the internal representation of the private use Fp escape sequences, these are an ESC followed by a
+/// single character in the range 0x30-0x3F.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum FpEscapeSequence {
+    BackIndex,
+    /// DECSC saves the cursor position and its attributes
+    SaveCursor,
+    /// DECRC restores what was saved by DECSC
+    RestoreCursor,
+    ForwardIndex,
+    ApplicationKeypad,
+    NormalKeypad,
+}
+
+impl FpEscapeSequence {
+    /// Returns the FpEscapeSequence that is identified by the character that follows the ESC
+    pub fn from_final(c: char) -> Option<FpEscapeSequence> {
+        match c {
+            '6' => Some(FpEscapeSequence::BackIndex),
+            '7' => Some(FpEscapeSequence::SaveCursor),
+            '8' => Some(FpEscapeSequence::RestoreCursor),
+            '9' => Some(FpEscapeSequence::ForwardIndex),
+            '=' => Some(FpEscapeSequence::ApplicationKeypad),
+            '>' => Some(FpEscapeSequence::NormalKeypad),
+            _ => None,
+        }
+    }
+}
+
+/// This is the internal representation of the Fs escape sequences, these are an ESC followed by a single
+/// character in the range 0x60-0x7E and are control functions that are not part of the C1 set.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum FsEscapeSequence {
+    DisableManualInput,
+    InterruptInterrupt,
+    EnableManualInput,
+    /// RIS puts the terminal back into the state it was in when it was first turned on
+    ResetToInitialState,
+    CodingMethodDelimiter,
+    LockingShiftTwo,
+    LockingShiftThree,
+    LockingShiftThreeRight,
+    LockingShiftTwoRight,
+    LockingShiftOneRight,
+}
+
+impl FsEscapeSequence {
+    /// Returns the FsEscapeSequence that is identified by the character that follows the ESC
+    pub fn from_final(c: char) -> Option<FsEscapeSequence> {
+        match c {
+            '`' => Some(FsEscapeSequence::DisableManualInput),
+            'a' => Some(FsEscapeSequence::InterruptInterrupt),
+            'b' => Some(FsEscapeSequence::EnableManualInput),
+            'c' => Some(FsEscapeSequence::ResetToInitialState),
+            'd' => Some(FsEscapeSequence::CodingMethodDelimiter),
+            'n' => Some(FsEscapeSequence::LockingShiftTwo),
+            'o' => Some(FsEscapeSequence::LockingShiftThree),
+            '|' => Some(FsEscapeSequence::LockingShiftThreeRight),
+            '}' => Some(FsEscapeSequence::LockingShiftTwoRight),
+            '~' => Some(FsEscapeSequence::LockingShiftOneRight),
+            _ => None,
+        }
+    }
+}
+
+/// This is the internal representation of the nF escape sequences, these are an ESC followed by one or more
+/// intermediate characters (0x20-0x2F) and a final character (0x30-0x7E).
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum NfEscapeSequence {
+    /// `ESC ( F` to `ESC + F` designate a 94 character set to G0 to G3 and `ESC - F` to `ESC / F` designate a
+    /// 96 character set to G1 to G3. `charset` holds any further intermediates along with the final character
+    /// e.g. `B` for ASCII or `0` for the DEC line drawing set.
+    DesignateCharacterSet { slot: u8, charset: String },
+    /// DECDHL `ESC # 3` the line is the top half of double height text
+    DoubleHeightTop,
+    /// DECDHL `ESC # 4` the line is the bottom half of double height text
+    DoubleHeightBottom,
+    /// DECSWL `ESC # 5`
+    SingleWidthLine,
+    /// DECDWL `ESC # 6`
+    DoubleWidthLine,
+    /// DECALN `ESC # 8` fills the screen with `E`
+    ScreenAlignmentTest,
+    Other {
+        intermediates: String,
+        final_byte: char,
+    },
+}
+
+impl NfEscapeSequence {
+    /// Converts the intermediates and final character that followed the ESC into its internal representation
+    pub fn from(intermediates: &str, final_byte: char) -> NfEscapeSequence {
+        let mut chars = intermediates.chars();
+        match (chars.next(), chars.as_str(), final_byte) {
+            (Some(c @ '('..='+'), rest, _) => NfEscapeSequence::DesignateCharacterSet {
+                slot: c as u8 - b'(',
+                charset: format!("{}{}", rest, final_byte),
+            },
+            (Some(c @ '-'..='/'), rest, _) => NfEscapeSequence::DesignateCharacterSet {
+                slot: c as u8 - b'-' + 1,
+                charset: format!("{}{}", rest, final_byte),
+            },
+            (Some('#'), "", '3') => NfEscapeSequence::DoubleHeightTop,
+            (Some('#'), "", '4') => NfEscapeSequence::DoubleHeightBottom,
+            (Some('#'), "", '5') => NfEscapeSequence::SingleWidthLine,
+            (Some('#'), "", '6') => NfEscapeSequence::DoubleWidthLine,
+            (Some('#'), "", '8') => NfEscapeSequence::ScreenAlignmentTest,
+            _ => NfEscapeSequence::Other {
+                intermediates: intermediates.to_string(),
+                final_byte,
+            },
+        }
+    }
+}
+
+/// This is every escape sequence we understand grouped by the kind of character that follows the ESC
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum EscapeSequence {
+    Fe(FeEscapeSequence),
+    Fp(FpEscapeSequence),
+    Fs(FsEscapeSequence),
+    Nf(NfEscapeSequence),
+}
+
+impl EscapeSequence {
+    /// Parses the escape sequence at the start of `chars`, the ESC is expected to have already been consumed.
+    pub fn from(chars: &mut Chars) -> Option<EscapeSequence> {
+        Parser::with_state(State::Escape).next_sequence(chars)
+    }
+
+    /// This is varient of from that returns a copy of the string given with the escape sequence removed if there was one at the start as well as the escape sequence found.
+    pub fn extract_from(string: &str) -> (String, Option<EscapeSequence>) {
+        let mut chars = string.chars();
+        let esc_seq = EscapeSequence::from(&mut chars);
+        (chars.as_str().to_string(), esc_seq)
+    }
+}
+
+/// The commands that take a string as their argument, each of these runs until a string terminator (ST) or BEL.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum StringCommand {
+    DeviceControlString,
+    OperatingSystemCommand,
+    StartOfString,
+    PrivacyMessage,
+    ApplicationProgramCommand,
+}
+
+/// The states of the ANSI parser, these follow the DEC VT500 model of an ECMA-48 parser.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum State {
+    Ground,
+    Escape,
+    /// An intermediate character has been read after the ESC making this an nF escape sequence
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    String(StringCommand),
+    /// An ESC has been read inside of a string, this is either the start of the string terminator or the
+    /// string has been cut off by a new escape sequence.
+    StringEscape(StringCommand),
+}
+
+/// What the parser should do when it is given bytes that are not valid UTF-8
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum InvalidUtf8 {
+    /// Replace each invalid sequence with the replacement character U+FFFD
+    Replace,
+    /// Pass each invalid byte through as the character with the same value, this is the same as reading the
+    /// byte as Latin-1
+    Raw,
+    /// Write each invalid byte out in the form `\xNN`
+    Hex,
+}
+
+/// The character encoding of the bytes given to the parser
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum Encoding {
+    Utf8,
+    /// Each byte is the character with the same value, this is how the 8-bit C1 controls are normally sent
+    Latin1,
+}
+
+/// A table driven parser for ANSI text.
+///
+/// The parser can be fed its input in arbitrary chunks and keeps its state between calls so a sequence that is
+/// split across two chunks is parsed exactly the same as if it had been given in one go. This also applies to
+/// UTF-8 characters when reading in bytes.
+#[derive(Debug, Clone)]
+pub struct Parser {
+    state: State,
+    params: String,
+    intermediates: String,
+    string_buffer: String,
+    text_buffer: String,
+    invalid_utf8: InvalidUtf8,
+    pending_bytes: Vec<u8>,
+    encoding: Encoding,
+    c1_controls: bool,
+    literal_escapes: Option<LiteralEscapes>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+impl Parser {
+    /// returns a new parser that is waiting for text
+    pub fn new() -> Parser {
+        Parser::with_state(State::Ground)
+    }
+
+    fn with_state(state: State) -> Parser {
+        Parser {
+            state,
+            params: String::new(),
+            intermediates: String::new(),
+            string_buffer: String::new(),
+            text_buffer: String::new(),
+            invalid_utf8: InvalidUtf8::Replace,
+            pending_bytes: Vec::new(),
+            encoding: Encoding::Utf8,
+            c1_controls: false,
+            literal_escapes: None,
+        }
+    }
+
+    /// Sets how bytes that are not valid UTF-8 are handled by read_bytes
+    pub fn with_invalid_utf8(mut self, invalid_utf8: InvalidUtf8) -> Parser {
+        self.invalid_utf8 = invalid_utf8;
+        self
+    }
+
+    /// Sets the encoding that read_bytes decodes its input with
+    pub fn with_encoding(mut self, encoding: Encoding) -> Parser {
+        self.encoding = encoding;
+        self
+    }
+
+    /// When enabled the 8-bit C1 control characters U+0080 to U+009F are treated the same as their 7-bit ESC
+    /// forms e.g. U+009B is the same as `ESC [`. This is off by default as these characters are rarely used as
+    /// controls.
+    pub fn with_c1_controls(mut self, c1_controls: bool) -> Parser {
+        self.c1_controls = c1_controls;
+        self
+    }
+
+    /// When enabled the textual notations of the escape character such as `\033` or `\u001b` are read as an
+    /// escape character, see [`LiteralEscapes`] for when a notation is decoded.
+    pub fn with_literal_escapes(mut self, literal_escapes: bool) -> Parser {
+        self.literal_escapes = literal_escapes.then(LiteralEscapes::new);
+        self
+    }
+
+    /// Parses the chunk of text given appending the elements found to `text`.
+    ///
+    /// Any text that is read is pushed out at the end of the chunk however an escape sequence that has not yet
+    /// finished will be held on to until the next call.
+    pub fn read(&mut self, chunk: &str, text: &mut Text) {
+        self.read_chars(chunk.chars(), text);
+        self.push_text(text);
+    }
+
+    /// Parses a chunk of UTF-8 encoded bytes appending the elements found to `text`.
+    ///
+    /// A character that is cut off at the end of the chunk is held on to until the next call, any bytes that
+    /// can not be decoded are handled according to the InvalidUtf8 policy of the parser.
+    pub fn read_bytes(&mut self, chunk: &[u8], text: &mut Text) {
+        if self.encoding == Encoding::Latin1 {
+            self.read_chars(chunk.iter().map(|b| char::from(*b)), text);
+            self.push_text(text);
+            return;
+        }
+        let mut bytes = std::mem::take(&mut self.pending_bytes);
+        bytes.extend_from_slice(chunk);
+        let mut remaining = bytes.as_slice();
+        loop {
+            match std::str::from_utf8(remaining) {
+                Ok(valid) => {
+                    self.read_chars(valid.chars(), text);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, invalid) = remaining.split_at(e.valid_up_to());
+                    // this can not fail as it has just been validated
+                    self.read_chars(std::str::from_utf8(valid).unwrap_or("").chars(), text);
+                    match e.error_len() {
+                        Some(len) => {
+                            self.read_invalid(&invalid[..len], text);
+                            remaining = &invalid[len..];
+                        }
+                        None => {
+                            self.pending_bytes = invalid.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.push_text(text);
+    }
+
+    /// Lets the parser know that there is no more input, any partial character that is being held on to is
+    /// treated as invalid.
+    pub fn finish(&mut self, text: &mut Text) {
+        let pending = std::mem::take(&mut self.pending_bytes);
+        if !pending.is_empty() {
+            self.read_invalid(&pending, text);
+        }
+        if let Some(literal_escapes) = self.literal_escapes.as_mut() {
+            for c in literal_escapes.finish().chars() {
+                self.read_char(c, text);
+            }
+        }
+        self.push_text(text);
+    }
+
+    fn read_invalid(&mut self, invalid: &[u8], text: &mut Text) {
+        match self.invalid_utf8 {
+            InvalidUtf8::Replace => self.read_chars(std::iter::once('\u{FFFD}'), text),
+            InvalidUtf8::Raw => self.read_chars(invalid.iter().map(|b| char::from(*b)), text),
+            InvalidUtf8::Hex => {
+                for b in invalid {
+                    self.read_chars(format!("\\x{:02X}", b).chars(), text);
+                }
+            }
+        }
+    }
+
+    fn read_chars(&mut self, chars: impl Iterator<Item = char>, text: &mut Text) {
+        for c in chars {
+            match self.literal_escapes.as_mut() {
+                Some(literal_escapes) => {
+                    for c in literal_escapes.decode(c).chars() {
+                        self.read_char(c, text);
+                    }
+                }
+                None => self.read_char(c, text),
+            }
+        }
+    }
+
+    fn read_char(&mut self, c: char, text: &mut Text) {
+        if let Some(sequence) = self.advance(c) {
+            self.push_text(text);
+            text.text.push(TextElement::Marker(sequence));
+        }
+    }
+
+    /// Reads characters until a single escape sequence has been completed, this is used to parse a sequence
+    /// where the introducer has already been consumed by the caller.
+    fn next_sequence(&mut self, chars: &mut Chars) -> Option<EscapeSequence> {
+        for c in chars.by_ref() {
+            if let Some(sequence) = self.advance(c) {
+                return Some(sequence);
+            }
+            if self.state == State::Ground {
+                break;
+            }
+        }
+        None
+    }
+
+    fn push_text(&mut self, text: &mut Text) {
+        if !self.text_buffer.is_empty() {
+            text.text
+                .push(TextElement::Text(std::mem::take(&mut self.text_buffer)));
+        }
+    }
+
+    fn enter(&mut self, state: State) {
+        self.state = state;
+        self.params.clear();
+        self.intermediates.clear();
+        self.string_buffer.clear();
+    }
+
+    /// Moves the parser on by a single character returning an escape sequence if this character completed one.
+    fn advance(&mut self, c: char) -> Option<EscapeSequence> {
+        if self.c1_controls && ('\u{80}'..='\u{9f}').contains(&c) {
+            // a C1 control is the same as an ESC followed by the character 0x40 below it
+            self.advance(ESC);
+            return self.advance(char::from(c as u8 - 0x40));
+        }
+        if c == ESC {
+            match self.state {
+                // this may be the start of the string terminator so hold on to the string until we know
+                State::String(command) => self.state = State::StringEscape(command),
+                // a doubled ESC is how an ESC is passed through inside of a string e.g. tmux passthrough
+                State::StringEscape(command) => {
+                    self.state = State::String(command);
+                    self.push_string(c);
+                }
+                _ => self.enter(State::Escape),
+            }
+            return None;
+        }
+        // CAN and SUB abort any sequence that is in progress
+        if (c == CAN || c == SUB) && self.state != State::Ground {
+            self.enter(State::Ground);
+            return None;
+        }
+        match self.state {
+            State::Ground => {
+                self.text_buffer.push(c);
+                None
+            }
+            State::Escape => self.escape(c),
+            State::EscapeIntermediate => self.escape_intermediate(c),
+            State::CsiEntry | State::CsiParam | State::CsiIntermediate | State::CsiIgnore => {
+                self.control_sequence(c)
+            }
+            State::String(command) => match c {
+                c if c == BEL => self.string_command(command),
+                '\x00'..='\x1f' if command == StringCommand::OperatingSystemCommand => None,
+                _ => {
+                    self.push_string(c);
+                    None
+                }
+            },
+            State::StringEscape(command) => {
+                if c == '\\' {
+                    self.string_command(command)
+                } else {
+                    // the string was cut off by the start of another escape sequence
+                    self.enter(State::Escape);
+                    self.escape(c)
+                }
+            }
+        }
+    }
+
+    fn escape(&mut self, c: char) -> Option<EscapeSequence> {
+        match c {
+            '\x00'..='\x1f' => {
+                self.text_buffer.push(c);
+                None
+            }
+            '\x7f' => None,
+            '\x20'..='\x2f' => {
+                self.intermediates.push(c);
+                self.state = State::EscapeIntermediate;
+                None
+            }
+            '[' => {
+                self.enter(State::CsiEntry);
+                None
+            }
+            'P' => {
+                self.enter(State::String(StringCommand::DeviceControlString));
+                None
+            }
+            ']' => {
+                self.enter(State::String(StringCommand::OperatingSystemCommand));
+                None
+            }
+            'X' => {
+                self.enter(State::String(StringCommand::StartOfString));
+                None
+            }
+            '^' => {
+                self.enter(State::String(StringCommand::PrivacyMessage));
+                None
+            }
+            '_' => {
+                self.enter(State::String(StringCommand::ApplicationProgramCommand));
+                None
+            }
+            '0'..='?' => {
+                self.enter(State::Ground);
+                FpEscapeSequence::from_final(c).map(EscapeSequence::Fp)
+            }
+            '`'..='~' => {
+                self.enter(State::Ground);
+                FsEscapeSequence::from_final(c).map(EscapeSequence::Fs)
+            }
+            '\x40'..='\x5f' => {
+                self.enter(State::Ground);
+                FeEscapeSequence::from_final(c).map(EscapeSequence::Fe)
+            }
+            // anything else can't be part of a sequence so it is printed like it would be in Ground
+            _ => {
+                self.enter(State::Ground);
+                self.advance(c)
+            }
+        }
+    }
+
+    fn escape_intermediate(&mut self, c: char) -> Option<EscapeSequence> {
+        match c {
+            '\x00'..='\x1f' => self.text_buffer.push(c),
+            '\x20'..='\x2f' => self.intermediates.push(c),
+            '\x30'..='\x7e' => {
+                let intermediates = std::mem::take(&mut self.intermediates);
+                self.enter(State::Ground);
+                return Some(EscapeSequence::Nf(NfEscapeSequence::from(
+                    &intermediates,
+                    c,
+                )));
+            }
+            '\x7f' => {}
+            _ => {
+                self.enter(State::Ground);
+                return self.advance(c);
+            }
+        }
+        None
+    }
+
+    /// Adds a character to the string that is being read, giving up on the string and returning it as text once it
+    /// grows past MAX_STRING_LENGTH
+    fn push_string(&mut self, c: char) {
+        self.string_buffer.push(c);
+        if self.string_buffer.len() > MAX_STRING_LENGTH {
+            let payload = std::mem::take(&mut self.string_buffer);
+            self.text_buffer.push_str(&payload);
+            self.enter(State::Ground);
+        }
+    }
+
+    fn string_command(&mut self, command: StringCommand) -> Option<EscapeSequence> {
+        let payload = std::mem::take(&mut self.string_buffer);
+        self.enter(State::Ground);
+        Some(EscapeSequence::Fe(match command {
+            StringCommand::DeviceControlString => FeEscapeSequence::DeviceControlString(payload),
+            StringCommand::OperatingSystemCommand => {
+                FeEscapeSequence::OperatingSystemCommand(OperatingSystemCommand::from(&payload))
+            }
+            StringCommand::StartOfString => FeEscapeSequence::StartOfString(payload),
+            StringCommand::PrivacyMessage => FeEscapeSequence::PrivacyMessage(payload),
+            StringCommand::ApplicationProgramCommand => {
+                FeEscapeSequence::ApplicationProgramCommand(payload)
+            }
+        }))
+    }
+
+    fn control_sequence(&mut self, c: char) -> Option<EscapeSequence> {
+        match c {
+            '\x00'..='\x1f' => self.text_buffer.push(c),
+            '\x7f' => {}
+            '\x20'..='\x2f' => {
+                if self.state != State::CsiIgnore {
+                    self.intermediates.push(c);
+                    self.state = State::CsiIntermediate;
+                }
+            }
+            '0'..='9' | ':' | ';' => match self.state {
+                State::CsiEntry | State::CsiParam => {
+                    self.params.push(c);
+                    self.state = State::CsiParam;
+                }
+                _ => self.state = State::CsiIgnore,
+            },
+            '<'..='?' if self.state == State::CsiEntry => {
+                self.params.push(c);
+                self.state = State::CsiParam;
+            }
+            '\x40'..='\x7e' => {
+                let ignored = self.state == State::CsiIgnore;
+                let mut params = std::mem::take(&mut self.params);
+                let intermediates = std::mem::take(&mut self.intermediates);
+                self.enter(State::Ground);
+                if ignored {
+                    return None;
+                }
+                return ControlSequence::dispatch(&mut params, &intermediates, c).map(
+                    |control_sequence| {
+                        EscapeSequence::Fe(FeEscapeSequence::ControlSequence(control_sequence))
+                    },
+                );
+            }
+            _ => self.state = State::CsiIgnore,
+        }
+        None
     }
 }
 
-pub type TextElement = crate::common::TextElement<FeEscapeSequence>;
+pub type TextElement = crate::common::TextElement<EscapeSequence>;
 
-pub type Text = crate::common::Text<FeEscapeSequence>;
+pub type Text = crate::common::Text<EscapeSequence>;
+
+impl Default for Text {
+    fn default() -> Self {
+        Text::new()
+    }
+}
 
 impl Text {
     // returns a new fully allocated ansi text struct
@@ -443,7 +1215,7 @@ impl Text {
     pub fn from(text: String) -> Text {
         let mut ansi_text = Text::new();
         ansi_text.read(text);
-        return ansi_text;
+        ansi_text
     }
 
     /// This allows us to read in a complient ANSI String into our internal representation it does this by parsing out the ansi escape sequences that follow the ebnf given below
@@ -476,29 +1248,10 @@ impl Text {
     /// esc_sequence = esc,fe_escape_sequence
     /// text = {string | esc_sequence}
     /// ```
+    ///
+    /// The whole string is treated as one chunk, to read text that arrives in pieces use a [`Parser`] directly.
     pub fn read(&mut self, text: String) {
-        let mut sequences = text.split(ESC);
-        match sequences.next() {
-            Some(text) => {
-                let s = text.to_string();
-                if !s.is_empty() {
-                    self.text.push(TextElement::Text(s));
-                }
-            }
-            None => {}
-        };
-        for sequence in sequences {
-            if sequence.len() <= 0 {
-                continue;
-            }
-            let (text, opt_fe_sequence) = FeEscapeSequence::extract_from(sequence);
-            if let Some(fe_sequence) = opt_fe_sequence {
-                self.text.push(TextElement::Marker(fe_sequence));
-            }
-            if !text.is_empty() {
-                self.text.push(TextElement::Text(text))
-            }
-        }
+        Parser::new().read(&text, self);
     }
 
     /// This clears the buffer of that is held internally is the same as allocating a new struct however it allocation than the creating a new vector.
@@ -508,31 +1261,22 @@ impl Text {
 }
 
 #[cfg(test)]
+#[allow(
+    clippy::unnecessary_cast,
+    clippy::len_zero,
+    clippy::useless_format,
+    clippy::unnecessary_mut_passed,
+    clippy::needless_borrow
+)]
 mod test {
     use std::{collections::HashMap, vec};
 
     use crate::{common, input_fmt::ansi::TextElement};
 
-    use super::{Color, ControlSequence, FeEscapeSequence, SelectGraphicRendition};
-
-    #[test]
-    fn color_from_index() {
-        let tests = [
-            (0, Some(common::black())),
-            (1, Some(common::red())),
-            (2, Some(common::green())),
-            (3, Some(common::yellow())),
-            (4, Some(common::blue())),
-            (5, Some(common::magenta())),
-            (6, Some(common::cyan())),
-            (7, Some(common::white())),
-            (8, None),
-        ];
-        for test in tests {
-            let (index, expected_result) = test;
-            assert_eq!(Color::from_index(index), expected_result);
-        }
-    }
+    use super::{
+        Color, ControlSequence, DecPrivateMode, EscapeSequence, FeEscapeSequence, FpEscapeSequence,
+        FsEscapeSequence, Modes, NfEscapeSequence, OperatingSystemCommand, SelectGraphicRendition,
+    };
 
     #[test]
     fn color_from_args() {
@@ -547,11 +1291,11 @@ mod test {
                     args.reverse();
                     assert_eq!(
                         Color::from_args(&mut args),
-                        Some(Color {
+                        Some(Color::Rgb(common::Color {
                             red: r,
                             green: g,
                             blue: b
-                        })
+                        }))
                     )
                 }
             }
@@ -561,15 +1305,9 @@ mod test {
             let mut args = base_8_bit.clone();
             args.push(c);
             args.reverse();
-            assert_eq!(
-                Color::from_args(&mut args),
-                Some(Color {
-                    red: (c >> 5) * 32,
-                    green: ((c & 28) >> 2) * 32,
-                    blue: (c & 3) * 32,
-                })
-            )
+            assert_eq!(Color::from_args(&mut args), Some(Color::Indexed(c)))
         }
+        assert_eq!(Color::from_args(&mut vec![5]), Some(Color::Indexed(0)));
     }
 
     #[test]
@@ -607,35 +1345,51 @@ mod test {
             (29 as u8, vec![SelectGraphicRendition::NotCrossedOut]),
             (
                 30 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Color::from_index(0))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    0,
+                )))],
             ),
             (
                 31 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Color::from_index(1))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    1,
+                )))],
             ),
             (
                 32 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Color::from_index(2))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    2,
+                )))],
             ),
             (
                 33 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Color::from_index(3))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    3,
+                )))],
             ),
             (
                 34 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Color::from_index(4))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    4,
+                )))],
             ),
             (
                 35 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Color::from_index(5))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    5,
+                )))],
             ),
             (
                 36 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Color::from_index(6))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    6,
+                )))],
             ),
             (
                 37 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Color::from_index(7))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    7,
+                )))],
             ),
             (
                 38 as u8,
@@ -646,51 +1400,51 @@ mod test {
             (39 as u8, vec![SelectGraphicRendition::ForgroundColor(None)]),
             (
                 40 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Color::from_index(
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
                     0,
-                ))],
+                )))],
             ),
             (
                 41 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Color::from_index(
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
                     1,
-                ))],
+                )))],
             ),
             (
                 42 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Color::from_index(
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
                     2,
-                ))],
+                )))],
             ),
             (
                 43 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Color::from_index(
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
                     3,
-                ))],
+                )))],
             ),
             (
                 44 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Color::from_index(
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
                     4,
-                ))],
+                )))],
             ),
             (
                 45 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Color::from_index(
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
                     5,
-                ))],
+                )))],
             ),
             (
                 46 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Color::from_index(
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
                     6,
-                ))],
+                )))],
             ),
             (
                 47 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Color::from_index(
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
                     7,
-                ))],
+                )))],
             ),
             (
                 48 as u8,
@@ -747,104 +1501,103 @@ mod test {
             ),
             (
                 90 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Some(
-                    Color::make_bright(common::black()),
-                ))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    8,
+                )))],
             ),
             (
                 91 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Some(
-                    Color::make_bright(common::red()),
-                ))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    9,
+                )))],
             ),
             (
                 92 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Some(
-                    Color::make_bright(common::green()),
-                ))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    10,
+                )))],
             ),
             (
                 93 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Some(
-                    Color::make_bright(common::yellow()),
-                ))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    11,
+                )))],
             ),
             (
                 94 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Some(
-                    Color::make_bright(common::blue()),
-                ))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    12,
+                )))],
             ),
             (
                 95 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Some(
-                    Color::make_bright(common::magenta()),
-                ))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    13,
+                )))],
             ),
             (
                 96 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Some(
-                    Color::make_bright(common::cyan()),
-                ))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    14,
+                )))],
             ),
             (
                 97 as u8,
-                vec![SelectGraphicRendition::ForgroundColor(Some(
-                    Color::make_bright(common::white()),
-                ))],
+                vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(
+                    15,
+                )))],
             ),
             (
                 100 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Some(
-                    Color::make_bright(common::black()),
-                ))],
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
+                    8,
+                )))],
             ),
             (
                 101 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Some(
-                    Color::make_bright(common::red()),
-                ))],
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
+                    9,
+                )))],
             ),
             (
                 102 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Some(
-                    Color::make_bright(common::green()),
-                ))],
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
+                    10,
+                )))],
             ),
             (
                 103 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Some(
-                    Color::make_bright(common::yellow()),
-                ))],
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
+                    11,
+                )))],
             ),
             (
                 104 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Some(
-                    Color::make_bright(common::blue()),
-                ))],
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
+                    12,
+                )))],
             ),
             (
                 105 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Some(
-                    Color::make_bright(common::magenta()),
-                ))],
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
+                    13,
+                )))],
             ),
             (
                 106 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Some(
-                    Color::make_bright(common::cyan()),
-                ))],
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
+                    14,
+                )))],
             ),
             (
                 107 as u8,
-                vec![SelectGraphicRendition::BackgroundColor(Some(
-                    Color::make_bright(common::white()),
-                ))],
+                vec![SelectGraphicRendition::BackgroundColor(Some(Color::Named(
+                    15,
+                )))],
             ),
         ]);
         for i in 0..=255 as u8 {
-            let mut args: Vec<u8> = Vec::new();
-            args.push(i);
+            let mut args: Vec<Vec<u16>> = vec![vec![u16::from(i)]];
             let result = SelectGraphicRendition::from(&mut args);
             match results.get(&i) {
                 Some(expected_result) => {
@@ -856,6 +1609,126 @@ mod test {
             }
         }
     }
+    #[test]
+    fn sgr_from_sub_args() {
+        let red = Some(Color::Rgb(common::Color {
+            red: 255,
+            green: 128,
+            blue: 0,
+        }));
+        for (args, expected_result) in [
+            (
+                "38:2::255:128:0",
+                vec![SelectGraphicRendition::ForgroundColor(red)],
+            ),
+            (
+                "38:2:255:128:0",
+                vec![SelectGraphicRendition::ForgroundColor(red)],
+            ),
+            (
+                "48:2:1:255:128:0",
+                vec![SelectGraphicRendition::BackgroundColor(red)],
+            ),
+            (
+                "38;2;255;128;0",
+                vec![SelectGraphicRendition::ForgroundColor(red)],
+            ),
+            (
+                "58:5:196",
+                vec![SelectGraphicRendition::SetUnderlineColor(Color::from_args(
+                    &mut vec![196, 5],
+                ))],
+            ),
+            (
+                "1;58;5;196;3",
+                vec![
+                    SelectGraphicRendition::Bold,
+                    SelectGraphicRendition::SetUnderlineColor(Color::from_args(&mut vec![196, 5])),
+                    SelectGraphicRendition::Italic,
+                ],
+            ),
+            ("4:0", vec![SelectGraphicRendition::NotUnderlined]),
+            ("4:1", vec![SelectGraphicRendition::Underline]),
+            ("4:2", vec![SelectGraphicRendition::DoublyUnderlined]),
+            ("4:3", vec![SelectGraphicRendition::CurlyUnderline]),
+            ("4:4", vec![SelectGraphicRendition::DottedUnderline]),
+            ("4:5", vec![SelectGraphicRendition::DashedUnderline]),
+            (
+                "4:3;38:2::255:128:0;1",
+                vec![
+                    SelectGraphicRendition::CurlyUnderline,
+                    SelectGraphicRendition::ForgroundColor(red),
+                    SelectGraphicRendition::Bold,
+                ],
+            ),
+        ] {
+            let mut args = ControlSequence::get_arg_groups(&mut args.to_string());
+            assert_eq!(SelectGraphicRendition::from(&mut args), expected_result);
+        }
+    }
+
+    #[test]
+    fn sgr_from_out_of_range_color() {
+        for (args, expected_result) in [
+            ("38;5;300", vec![]),
+            (
+                "1;38;5;300;3",
+                vec![SelectGraphicRendition::Bold, SelectGraphicRendition::Italic],
+            ),
+            ("48;2;300;0;0;4", vec![SelectGraphicRendition::Underline]),
+            ("38:2::0:256:0", vec![]),
+            ("58:5:1000;1", vec![SelectGraphicRendition::Bold]),
+            ("256;1", vec![SelectGraphicRendition::Bold]),
+        ] {
+            let mut args = ControlSequence::get_arg_groups(&mut args.to_string());
+            assert_eq!(SelectGraphicRendition::from(&mut args), expected_result);
+        }
+    }
+
+    #[test]
+    fn csi_get_arg_groups() {
+        assert_eq!(
+            ControlSequence::get_arg_groups(&mut "38:2::255:128:0;1".to_string()),
+            vec![vec![38, 2, 0, 255, 128, 0], vec![1]]
+        );
+        assert_eq!(
+            ControlSequence::get_arg_groups(&mut "4:3;".to_string()),
+            vec![vec![4, 3]]
+        );
+        assert_eq!(
+            ControlSequence::get_args(&mut "4:3;1".to_string()),
+            vec![4, 1]
+        );
+    }
+
+    #[test]
+    fn csi_modes() {
+        for (mut test, expected_result) in [
+            (
+                "?1000;1002;1006h".chars(),
+                vec![
+                    DecPrivateMode::NormalMouseTracking,
+                    DecPrivateMode::ButtonEventMouseTracking,
+                    DecPrivateMode::SgrMouse,
+                ],
+            ),
+            (
+                "?2026;9999l".chars(),
+                vec![DecPrivateMode::SynchronizedOutput],
+            ),
+            (">4;2h".chars(), vec![]),
+            ("4h".chars(), vec![]),
+        ] {
+            let result = match ControlSequence::from(&mut test) {
+                Some(ControlSequence::SetMode(modes)) | Some(ControlSequence::ResetMode(modes)) => {
+                    modes.dec_private_modes()
+                }
+                _ => panic!("expected a set or reset mode sequence"),
+            };
+            assert_eq!(result, expected_result);
+        }
+    }
+
     #[test]
     fn csi_get_args() {
         assert_eq!(ControlSequence::get_args(&mut "3".to_string()), vec![3]);
@@ -872,11 +1745,15 @@ mod test {
             ControlSequence::get_args(&mut "255;;255".to_string()),
             vec![255, 0, 255]
         );
+        assert_eq!(
+            ControlSequence::get_args(&mut "300;70000".to_string()),
+            vec![300, u16::MAX]
+        );
     }
 
     #[test]
     fn csi_from() {
-        for n in [0 as u8, 100 as u8, 255 as u8] {
+        for n in [0 as u16, 100 as u16, 255 as u16, 300 as u16, 65535 as u16] {
             for test_case in [
                 (
                     format!("{}A", n).chars(),
@@ -931,6 +1808,32 @@ mod test {
                     Some(ControlSequence::HorizonalVerticalPosition(n, 1)),
                 ),
                 (format!("5i").chars(), Some(ControlSequence::AUXPortOn)),
+                (
+                    format!("7d").chars(),
+                    Some(ControlSequence::VerticalPositionAbsolute(7)),
+                ),
+                (
+                    format!("2@").chars(),
+                    Some(ControlSequence::InsertCharacters(2)),
+                ),
+                (
+                    format!("2P").chars(),
+                    Some(ControlSequence::DeleteCharacters(2)),
+                ),
+                (
+                    format!("2X").chars(),
+                    Some(ControlSequence::EraseCharacters(2)),
+                ),
+                (format!("3L").chars(), Some(ControlSequence::InsertLines(3))),
+                (format!("3M").chars(), Some(ControlSequence::DeleteLines(3))),
+                (
+                    format!("2;20r").chars(),
+                    Some(ControlSequence::SetScrollingRegion(2, 20)),
+                ),
+                (
+                    format!("r").chars(),
+                    Some(ControlSequence::SetScrollingRegion(0, 0)),
+                ),
                 (format!("4i").chars(), Some(ControlSequence::AUXPortOff)),
                 (
                     format!("6n").chars(),
@@ -944,32 +1847,28 @@ mod test {
                     format!("u").chars(),
                     Some(ControlSequence::RestoreCursorPosistion),
                 ),
-                (format!("?25h").chars(), Some(ControlSequence::VT220Cursor)),
-                (format!("?25l").chars(), Some(ControlSequence::HideCursor)),
-                (
-                    format!("?1004h").chars(),
-                    Some(ControlSequence::EnableReportingFocus),
-                ),
                 (
-                    format!("?1004l").chars(),
-                    Some(ControlSequence::DisableReportingFocus),
-                ),
-                (
-                    format!("?1049h").chars(),
-                    Some(ControlSequence::EnableAltScreenBuf),
+                    format!("?25h").chars(),
+                    Some(ControlSequence::SetMode(Modes {
+                        private_marker: Some('?'),
+                        modes: vec![25],
+                    })),
                 ),
                 (
                     format!("?1049l").chars(),
-                    Some(ControlSequence::DisableAltScreenBuf),
-                ),
-                (
-                    format!("?2004h").chars(),
-                    Some(ControlSequence::BracketPasteMode),
+                    Some(ControlSequence::ResetMode(Modes {
+                        private_marker: Some('?'),
+                        modes: vec![1049],
+                    })),
                 ),
                 (
-                    format!("?2004l").chars(),
-                    Some(ControlSequence::NoBracketPasteMode),
+                    format!("4h").chars(),
+                    Some(ControlSequence::SetMode(Modes {
+                        private_marker: None,
+                        modes: vec![4],
+                    })),
                 ),
+                (format!("?5m").chars(), None),
             ] {
                 let (mut test, result) = test_case;
                 assert_eq!(ControlSequence::from(&mut test), result);
@@ -982,17 +1881,41 @@ mod test {
         for test_case in [
             ("Ntest".chars(), Some(FeEscapeSequence::SingleShiftTwo)),
             ("Otest".chars(), Some(FeEscapeSequence::SingleShiftThree)),
-            ("Ptest".chars(), Some(FeEscapeSequence::DeviceControlString)),
+            (
+                "Pq#0;2;0;0;0#0~~\u{001B}\\test".chars(),
+                Some(FeEscapeSequence::DeviceControlString(
+                    "q#0;2;0;0;0#0~~".to_string(),
+                )),
+            ),
             ("\\test".chars(), Some(FeEscapeSequence::StringTerminator)),
             (
-                "]test".chars(),
-                Some(FeEscapeSequence::OperatingSystemCommand),
+                "]0;title\u{0007}test".chars(),
+                Some(FeEscapeSequence::OperatingSystemCommand(
+                    OperatingSystemCommand::Other("0;title".to_string()),
+                )),
+            ),
+            (
+                "]8;;https://example.com\u{001B}\\test".chars(),
+                Some(FeEscapeSequence::OperatingSystemCommand(
+                    OperatingSystemCommand::Hyperlink {
+                        params: String::new(),
+                        uri: "https://example.com".to_string(),
+                    },
+                )),
+            ),
+            (
+                "Xstring\u{001B}\\test".chars(),
+                Some(FeEscapeSequence::StartOfString("string".to_string())),
+            ),
+            (
+                "^message\u{0007}test".chars(),
+                Some(FeEscapeSequence::PrivacyMessage("message".to_string())),
             ),
-            ("Xtest".chars(), Some(FeEscapeSequence::StartOfString)),
-            ("^test".chars(), Some(FeEscapeSequence::PrivacyMessage)),
             (
-                "_test".chars(),
-                Some(FeEscapeSequence::ApplicationProgramCommand),
+                "_Gf=100;AAAA\u{001B}\\test".chars(),
+                Some(FeEscapeSequence::ApplicationProgramCommand(
+                    "Gf=100;AAAA".to_string(),
+                )),
             ),
             (
                 "[5itest".chars(),
@@ -1004,7 +1927,7 @@ mod test {
                 "[31mtest".chars(),
                 Some(FeEscapeSequence::ControlSequence(
                     ControlSequence::SelectGraphicalRendition(vec![
-                        SelectGraphicRendition::ForgroundColor(Color::from_index(1)),
+                        SelectGraphicRendition::ForgroundColor(Some(Color::Named(1))),
                     ]),
                 )),
             ),
@@ -1018,13 +1941,38 @@ mod test {
     fn fe_extract_from() {
         for test_case in [
             ("Ntest", Some(FeEscapeSequence::SingleShiftTwo)),
+            ("Mtest", Some(FeEscapeSequence::ReverseIndex)),
             ("Otest", Some(FeEscapeSequence::SingleShiftThree)),
-            ("Ptest", Some(FeEscapeSequence::DeviceControlString)),
+            (
+                "Ptmux;\u{001B}\u{001B}[31m\u{001B}\\test",
+                Some(FeEscapeSequence::DeviceControlString(
+                    "tmux;\u{001B}[31m".to_string(),
+                )),
+            ),
             ("\\test", Some(FeEscapeSequence::StringTerminator)),
-            ("]test", Some(FeEscapeSequence::OperatingSystemCommand)),
-            ("Xtest", Some(FeEscapeSequence::StartOfString)),
-            ("^test", Some(FeEscapeSequence::PrivacyMessage)),
-            ("_test", Some(FeEscapeSequence::ApplicationProgramCommand)),
+            (
+                "]8;id=1;https://example.com/a;b\u{0007}test",
+                Some(FeEscapeSequence::OperatingSystemCommand(
+                    OperatingSystemCommand::Hyperlink {
+                        params: "id=1".to_string(),
+                        uri: "https://example.com/a;b".to_string(),
+                    },
+                )),
+            ),
+            (
+                "X\u{0007}test",
+                Some(FeEscapeSequence::StartOfString(String::new())),
+            ),
+            (
+                "^message\u{001B}\\test",
+                Some(FeEscapeSequence::PrivacyMessage("message".to_string())),
+            ),
+            (
+                "_command\u{0007}test",
+                Some(FeEscapeSequence::ApplicationProgramCommand(
+                    "command".to_string(),
+                )),
+            ),
             (
                 "[5itest",
                 Some(FeEscapeSequence::ControlSequence(
@@ -1035,7 +1983,7 @@ mod test {
                 "[31mtest",
                 Some(FeEscapeSequence::ControlSequence(
                     ControlSequence::SelectGraphicalRendition(vec![
-                        SelectGraphicRendition::ForgroundColor(Color::from_index(1)),
+                        SelectGraphicRendition::ForgroundColor(Some(Color::Named(1))),
                     ]),
                 )),
             ),
@@ -1047,20 +1995,340 @@ mod test {
         }
     }
 
+    #[test]
+    fn escape_extract_from() {
+        for test_case in [
+            (
+                "(Btest",
+                Some(EscapeSequence::Nf(
+                    NfEscapeSequence::DesignateCharacterSet {
+                        slot: 0,
+                        charset: "B".to_string(),
+                    },
+                )),
+            ),
+            (
+                ")0test",
+                Some(EscapeSequence::Nf(
+                    NfEscapeSequence::DesignateCharacterSet {
+                        slot: 1,
+                        charset: "0".to_string(),
+                    },
+                )),
+            ),
+            (
+                "-Atest",
+                Some(EscapeSequence::Nf(
+                    NfEscapeSequence::DesignateCharacterSet {
+                        slot: 1,
+                        charset: "A".to_string(),
+                    },
+                )),
+            ),
+            (
+                "#8test",
+                Some(EscapeSequence::Nf(NfEscapeSequence::ScreenAlignmentTest)),
+            ),
+            (
+                " Ftest",
+                Some(EscapeSequence::Nf(NfEscapeSequence::Other {
+                    intermediates: " ".to_string(),
+                    final_byte: 'F',
+                })),
+            ),
+            (
+                "7test",
+                Some(EscapeSequence::Fp(FpEscapeSequence::SaveCursor)),
+            ),
+            (
+                "8test",
+                Some(EscapeSequence::Fp(FpEscapeSequence::RestoreCursor)),
+            ),
+            (
+                "=test",
+                Some(EscapeSequence::Fp(FpEscapeSequence::ApplicationKeypad)),
+            ),
+            (
+                ">test",
+                Some(EscapeSequence::Fp(FpEscapeSequence::NormalKeypad)),
+            ),
+            (
+                "ctest",
+                Some(EscapeSequence::Fs(FsEscapeSequence::ResetToInitialState)),
+            ),
+            (
+                "ntest",
+                Some(EscapeSequence::Fs(FsEscapeSequence::LockingShiftTwo)),
+            ),
+            (
+                "Ntest",
+                Some(EscapeSequence::Fe(FeEscapeSequence::SingleShiftTwo)),
+            ),
+            ("1test", None),
+        ] {
+            let (test, expect_result) = test_case;
+            let (result_text, result) = EscapeSequence::extract_from(test);
+            assert_eq!(result_text, "test".to_string());
+            assert_eq!(result, expect_result);
+        }
+    }
+
     #[test]
     fn test_from() {
         assert_eq!(super::Text::from("\u{001B}[m\u{001B}[32mThis is a \u{001B}[1mtest\u{001B}[22m and it should work\u{001B}[0m".to_string()),super::Text{
             text:vec![
-                TextElement::Marker(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Normal]))),
-                TextElement::Marker(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::ForgroundColor(Color::from_index(2))]))),
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Normal])))),
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::ForgroundColor(Some(Color::Named(2)))])))),
                 TextElement::Text("This is a ".to_string()),
-                TextElement::Marker(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Bold]))),
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Bold])))),
                 TextElement::Text("test".to_string()),
-                TextElement::Marker(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::NormalIntensity]))),
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::NormalIntensity])))),
                 TextElement::Text(" and it should work".to_string()),
-                TextElement::Marker(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Normal]))),
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Normal])))),
                 ]
             }
         );
     }
+
+    #[test]
+    fn parser_unknown_final_byte() {
+        for test_case in [
+            ("\u{001B}[5xtest", "test"),
+            ("\u{001B}[1 qtest", "test"),
+            ("\u{001B}[?1;2$ptest", "test"),
+            ("\u{001B}]0;title\u{0007}test", "test"),
+            ("\u{001B}]0;title\u{001B}\\test", "test"),
+            ("\u{001B}[31\u{0018}test", "test"),
+            ("\u{001B}(Btest", "test"),
+            ("\u{001B})0\u{001B}7test\u{001B}8", "test"),
+            ("\u{001B}=\u{001B}>\u{001B}ctest", "test"),
+            ("\u{001B}#8test", "test"),
+            ("\u{001B}(%5test", "test"),
+            ("\u{001B}[38:2::255:128:0mtest", "test"),
+            ("\u{001B}[4:3mtest", "test"),
+            ("\u{001B}Pq#0;2;0;0;0#0~~-\u{001B}\\test", "test"),
+            ("\u{001B}_Gf=100;AAAA\u{001B}\\test", "test"),
+            (
+                "\u{001B}Ptmux;\u{001B}\u{001B}]0;x\u{0007}\u{001B}\\test",
+                "test",
+            ),
+        ] {
+            let (input, expected_result) = test_case;
+            let text = super::Text::from(input.to_string());
+            let result: String = text
+                .text
+                .iter()
+                .filter_map(|element| match element {
+                    TextElement::Text(t) => Some(t.clone()),
+                    TextElement::Marker(_) => None,
+                })
+                .collect();
+            assert_eq!(result, expected_result);
+        }
+    }
+
+    #[test]
+    fn parser_chunked_read() {
+        let input =
+            "\u{001B}[32mThis is a \u{001B}[1mtest\u{001B}[22m and it should work\u{001B}[0m";
+        let expected_result = super::Text::from(input.to_string());
+        for chunk_size in 1..input.len() {
+            let mut parser = super::Parser::new();
+            let mut text = super::Text::new();
+            let chars: Vec<char> = input.chars().collect();
+            for chunk in chars.chunks(chunk_size) {
+                parser.read(&chunk.iter().collect::<String>(), &mut text);
+            }
+            let mut merged = super::Text::new();
+            for element in text.text {
+                match (merged.text.last_mut(), element) {
+                    (Some(TextElement::Text(last)), TextElement::Text(t)) => last.push_str(&t),
+                    (_, element) => merged.text.push(element),
+                }
+            }
+            assert_eq!(merged, expected_result);
+        }
+    }
+
+    #[test]
+    fn parser_non_ascii_after_escape() {
+        for (input, expected_result) in [
+            ("\u{001B}\u{e9} test", "\u{e9} test"),
+            ("\u{001B}(\u{e9} test", "\u{e9} test"),
+        ] {
+            let text = super::Text::from(input.to_string());
+            assert_eq!(
+                text.text,
+                vec![TextElement::Text(expected_result.to_string())]
+            );
+        }
+    }
+
+    #[test]
+    fn parser_unterminated_string() {
+        let payload = "x".repeat(super::MAX_STRING_LENGTH + 1);
+        let mut text = super::Text::new();
+        super::Parser::new().read(&format!("\u{001B}]{}\u{001B}[1mtest", payload), &mut text);
+        assert_eq!(
+            text.text,
+            vec![
+                TextElement::Text(payload),
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(
+                    ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Bold])
+                ))),
+                TextElement::Text("test".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_read_bytes() {
+        for test_case in [
+            (super::InvalidUtf8::Replace, "caf\u{FFFD} \u{FFFD}\u{FFFD}é"),
+            (super::InvalidUtf8::Raw, "caf\u{E9} \u{FF}\u{C3}é"),
+            (super::InvalidUtf8::Hex, "caf\\xE9 \\xFF\\xC3é"),
+        ] {
+            let (invalid_utf8, expected_result) = test_case;
+            let input: &[u8] = b"caf\xE9 \x1b[1m\xFF\xC3\x1b[0m\xC3\xA9";
+            for chunk_size in 1..input.len() {
+                let mut parser = super::Parser::new().with_invalid_utf8(invalid_utf8);
+                let mut text = super::Text::new();
+                for chunk in input.chunks(chunk_size) {
+                    parser.read_bytes(chunk, &mut text);
+                }
+                parser.finish(&mut text);
+                let result: String = text
+                    .text
+                    .iter()
+                    .filter_map(|element| match element {
+                        TextElement::Text(t) => Some(t.clone()),
+                        TextElement::Marker(_) => None,
+                    })
+                    .collect();
+                assert_eq!(result, expected_result);
+            }
+        }
+    }
+
+    #[test]
+    fn parser_colors() {
+        let mut text = super::Text::new();
+        super::Parser::new().read("\x1b[31;102;38;5;3;48;2;1;2;3mx", &mut text);
+        assert_eq!(
+            text.text,
+            vec![
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(
+                    ControlSequence::SelectGraphicalRendition(vec![
+                        SelectGraphicRendition::ForgroundColor(Some(Color::Named(1))),
+                        SelectGraphicRendition::BackgroundColor(Some(Color::Named(10))),
+                        SelectGraphicRendition::ForgroundColor(Some(Color::Indexed(3))),
+                        SelectGraphicRendition::BackgroundColor(Some(Color::Rgb(common::Color {
+                            red: 1,
+                            green: 2,
+                            blue: 3
+                        }))),
+                    ])
+                ))),
+                TextElement::Text("x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_c1_controls() {
+        let input = "\u{9b}1mbold\u{9b}0m \u{9d}8;;https://example.com\u{9c}link";
+        let mut text = super::Text::new();
+        super::Parser::new()
+            .with_c1_controls(true)
+            .read(input, &mut text);
+        assert_eq!(
+            text.text,
+            vec![
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(
+                    ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Bold])
+                ))),
+                TextElement::Text("bold".to_string()),
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(
+                    ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Normal])
+                ))),
+                TextElement::Text(" ".to_string()),
+                TextElement::Marker(EscapeSequence::Fe(
+                    FeEscapeSequence::OperatingSystemCommand(OperatingSystemCommand::Hyperlink {
+                        params: String::new(),
+                        uri: "https://example.com".to_string(),
+                    })
+                )),
+                TextElement::Text("link".to_string()),
+            ]
+        );
+
+        let mut text = super::Text::new();
+        super::Parser::new().read(input, &mut text);
+        assert_eq!(text.text, vec![TextElement::Text(input.to_string())]);
+    }
+
+    #[test]
+    fn parser_latin1_c1_controls() {
+        let mut parser = super::Parser::new()
+            .with_encoding(super::Encoding::Latin1)
+            .with_c1_controls(true);
+        let mut text = super::Text::new();
+        parser.read_bytes(b"\x9b1mcaf\xe9\x9b0m", &mut text);
+        assert_eq!(
+            text.text,
+            vec![
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(
+                    ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Bold])
+                ))),
+                TextElement::Text("caf\u{e9}".to_string()),
+                TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(
+                    ControlSequence::SelectGraphicalRendition(vec![SelectGraphicRendition::Normal])
+                ))),
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_literal_escapes() {
+        let input: &[u8] = b"{\"log\":\"\\u001b[1mC:\\\\temp\\u001b[0m\"}\\";
+        for chunk_size in 1..input.len() {
+            let mut parser = super::Parser::new().with_literal_escapes(true);
+            let mut text = super::Text::new();
+            for chunk in input.chunks(chunk_size) {
+                parser.read_bytes(chunk, &mut text);
+            }
+            parser.finish(&mut text);
+            let markers = text
+                .text
+                .iter()
+                .filter(|element| matches!(element, TextElement::Marker(_)))
+                .count();
+            let result: String = text
+                .text
+                .iter()
+                .filter_map(|element| match element {
+                    TextElement::Text(t) => Some(t.clone()),
+                    TextElement::Marker(_) => None,
+                })
+                .collect();
+            assert_eq!(markers, 2);
+            assert_eq!(result, "{\"log\":\"C:\\\\temp\"}\\");
+        }
+    }
+
+    #[test]
+    fn parser_finish_incomplete_character() {
+        let mut parser = super::Parser::new();
+        let mut text = super::Text::new();
+        parser.read_bytes(b"test\xE2\x82", &mut text);
+        assert_eq!(text.text, vec![TextElement::Text("test".to_string())]);
+        parser.finish(&mut text);
+        assert_eq!(
+            text.text,
+            vec![
+                TextElement::Text("test".to_string()),
+                TextElement::Text("\u{FFFD}".to_string())
+            ]
+        );
+    }
 }
@@ -0,0 +1,130 @@
+/// The textual ways of writing the escape character that are decoded, these are found in JSON logs, console
+/// exports and shell scripts where the real escape character has been escaped.
+const NOTATIONS: [&str; 7] = ["\\033", "\\x1b", "\\x1B", "\\u001b", "\\u001B", "\\e", "^["];
+
+/// Replaces the literal notations of the escape character such as `\033` or `^[` with a real escape character.
+///
+/// A notation is only replaced when it is followed by `[` or `]` as these are what start a control sequence and
+/// an OSC, or by an escaped backslash (`\\`) which is the string terminator. This way a backslash that is part
+/// of the text e.g. a windows path is left alone. A doubled backslash is always read as an escaped backslash so
+/// `\\033[` is not decoded.
+///
+/// Characters are decoded one at a time so a notation that is split between two chunks is still decoded.
+#[derive(Debug, Clone, Default)]
+pub struct LiteralEscapes {
+    pending: String,
+}
+
+impl LiteralEscapes {
+    pub fn new() -> LiteralEscapes {
+        LiteralEscapes {
+            pending: String::new(),
+        }
+    }
+
+    /// Decodes the next character returning the characters that are ready to be parsed, characters that may
+    /// be part of a notation are held on to until we know if they are.
+    pub fn decode(&mut self, c: char) -> String {
+        if self.pending.is_empty() {
+            if c == '\\' || c == '^' {
+                self.pending.push(c);
+                return String::new();
+            }
+            return c.to_string();
+        }
+        if NOTATIONS.contains(&self.pending.as_str()) {
+            return match c {
+                '[' | ']' => {
+                    self.pending.clear();
+                    format!("\x1b{}", c)
+                }
+                // the caret notation is not escaped itself so neither is the backslash that follows it
+                '\\' if self.pending == "^[" => {
+                    self.pending.clear();
+                    "\x1b\\".to_string()
+                }
+                '\\' => {
+                    self.pending.push(c);
+                    String::new()
+                }
+                _ => self.flush(c),
+            };
+        }
+        if let Some(notation) = self.pending.strip_suffix('\\') {
+            if NOTATIONS.contains(&notation) {
+                if c == '\\' {
+                    self.pending.clear();
+                    return "\x1b\\".to_string();
+                }
+                return self.flush(c);
+            }
+        }
+        if self.pending == "\\" && c == '\\' {
+            self.pending.clear();
+            return "\\\\".to_string();
+        }
+        self.pending.push(c);
+        if NOTATIONS
+            .iter()
+            .any(|notation| notation.starts_with(self.pending.as_str()))
+        {
+            return String::new();
+        }
+        self.pending.pop();
+        self.flush(c)
+    }
+
+    /// Returns any characters that are being held on to, this should be called once there is no more input.
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// The pending characters are not a notation so the first one is passed through and the rest are decoded
+    /// again as they may be the start of a notation.
+    fn flush(&mut self, c: char) -> String {
+        let pending = std::mem::take(&mut self.pending);
+        let mut chars = pending.chars();
+        let mut decoded: String = chars.next().into_iter().collect();
+        for c in chars.chain(std::iter::once(c)) {
+            decoded.push_str(&self.decode(c));
+        }
+        decoded
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LiteralEscapes;
+
+    fn decode(input: &str) -> String {
+        let mut literal_escapes = LiteralEscapes::new();
+        let mut result: String = input.chars().map(|c| literal_escapes.decode(c)).collect();
+        result.push_str(&literal_escapes.finish());
+        result
+    }
+
+    #[test]
+    fn literal_escapes_decode() {
+        for (input, expected_result) in [
+            ("\\033[31merror\\033[0m", "\x1b[31merror\x1b[0m"),
+            ("\\x1b[1mbold\\x1B[0m", "\x1b[1mbold\x1b[0m"),
+            ("\"\\u001b[31merror\\u001b[0m\"", "\"\x1b[31merror\x1b[0m\""),
+            ("\\e[1m^[[0m", "\x1b[1m\x1b[0m"),
+            (
+                "\\u001b]8;;https://example.com\\u001b\\\\link",
+                "\x1b]8;;https://example.com\x1b\\link",
+            ),
+            ("^[]0;title^[\\", "\x1b]0;title\x1b\\"),
+            ("C:\\e\\x1b\\033", "C:\\e\\x1b\\033"),
+            ("\\\\033[31m", "\\\\033[31m"),
+            ("\\n\\t\\x\\u00", "\\n\\t\\x\\u00"),
+            ("x^2 ^^[", "x^2 ^^["),
+            ("^^[[1m", "^\x1b[1m"),
+            ("\\033\\033[1m", "\\033\x1b[1m"),
+            ("\\033\\x\\033[1m", "\\033\\x\x1b[1m"),
+            ("\\\\\\033[1m", "\\\\\x1b[1m"),
+        ] {
+            assert_eq!(decode(input), expected_result, "{}", input);
+        }
+    }
+}
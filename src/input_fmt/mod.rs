@@ -1 +1,3 @@
 pub mod ansi;
+pub mod literal;
+pub mod overstrike;
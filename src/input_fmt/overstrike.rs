@@ -0,0 +1,329 @@
+use crate::input_fmt::ansi::{
+    ControlSequence, EscapeSequence, FeEscapeSequence, SelectGraphicRendition, Text, TextElement,
+};
+
+/// This is an alias for the ASCII Backspace character
+static BS: char = '\x08';
+
+/// A character along with what it has been overstruck with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Overstruck {
+    c: char,
+    bold: bool,
+    underline: bool,
+}
+
+/// Turns the backspace overstrike used by nroff (e.g. `man` pages) into formatting.
+///
+/// A character that is struck over with itself `X\bX` is bold and a character struck over with an underscore
+/// `_\bX` is underlined, these can be combined e.g. `_\bX\bX`. The formatting is added as SGR sequences so the
+/// text returned can be converted like any other ansi::Text.
+///
+/// The overstrike formatting is a layer on top of the formatting set by the SGR sequences in the input, so when a
+/// run of overstruck text ends only what the overstrike added is taken away and any bold or underline from the
+/// input carries on.
+#[derive(Clone, Debug, Default)]
+pub struct Overstrike {
+    current: Option<Overstruck>,
+    backspace: bool,
+    bold: bool,
+    underline: bool,
+    /// The intensity set by the SGR sequences in the input
+    sgr_bold: bool,
+    sgr_faint: bool,
+    /// The input has underlined the text with an SGR sequence
+    sgr_underline: bool,
+}
+
+impl Overstrike {
+    pub fn new() -> Overstrike {
+        Overstrike {
+            current: None,
+            backspace: false,
+            bold: false,
+            underline: false,
+            sgr_bold: false,
+            sgr_faint: false,
+            sgr_underline: false,
+        }
+    }
+
+    /// Converts the text given, the last character is held on to as it may be struck over in the next part
+    pub fn convert(&mut self, text: Text) -> Text {
+        let mut result = Text::new();
+        for element in text.text {
+            match element {
+                TextElement::Text(string) => {
+                    for c in string.chars() {
+                        self.put_char(c, &mut result);
+                    }
+                }
+                TextElement::Marker(marker) => {
+                    // the overstrike formatting is taken off first so that it can't hide what the marker changes
+                    self.flush(&mut result);
+                    self.set_formats(false, false, &mut result);
+                    self.apply_marker(&marker);
+                    result.text.push(TextElement::Marker(marker));
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns anything that is being held on to once there is no more input
+    pub fn finish(&mut self) -> Text {
+        let mut result = Text::new();
+        self.flush(&mut result);
+        self.set_formats(false, false, &mut result);
+        result
+    }
+
+    fn put_char(&mut self, c: char, result: &mut Text) {
+        if c == BS && self.current.is_some() && !self.backspace {
+            self.backspace = true;
+            return;
+        }
+        if let (Some(current), true) = (self.current.as_mut(), self.backspace) {
+            self.backspace = false;
+            if c == current.c {
+                current.bold = true;
+                return;
+            } else if c == '_' {
+                current.underline = true;
+                return;
+            } else if current.c == '_' && !current.bold {
+                current.c = c;
+                current.underline = true;
+                return;
+            }
+            // this is not an overstrike so the backspace is passed through as it is
+            self.flush(result);
+            self.push_plain(BS, result);
+        }
+        self.flush(result);
+        match c {
+            // control characters can not be struck over so there is no need to hold on to them
+            '\x00'..='\x1f' | '\x7f' => self.push_plain(c, result),
+            _ => {
+                self.current = Some(Overstruck {
+                    c,
+                    bold: false,
+                    underline: false,
+                })
+            }
+        }
+    }
+
+    fn flush(&mut self, result: &mut Text) {
+        if let Some(current) = self.current.take() {
+            self.set_formats(current.bold, current.underline, result);
+            push_char(current.c, result);
+        }
+        if std::mem::take(&mut self.backspace) {
+            self.push_plain(BS, result);
+        }
+    }
+
+    fn push_plain(&mut self, c: char, result: &mut Text) {
+        self.set_formats(false, false, result);
+        push_char(c, result);
+    }
+
+    /// Keeps track of the bold and underline that the input sets itself
+    fn apply_marker(&mut self, marker: &EscapeSequence) {
+        if let EscapeSequence::Fe(FeEscapeSequence::ControlSequence(
+            ControlSequence::SelectGraphicalRendition(sgrs),
+        )) = marker
+        {
+            for sgr in sgrs {
+                match sgr {
+                    SelectGraphicRendition::Normal => {
+                        self.sgr_bold = false;
+                        self.sgr_faint = false;
+                        self.sgr_underline = false;
+                    }
+                    SelectGraphicRendition::Bold => self.sgr_bold = true,
+                    SelectGraphicRendition::Faint => self.sgr_faint = true,
+                    SelectGraphicRendition::NormalIntensity => {
+                        self.sgr_bold = false;
+                        self.sgr_faint = false;
+                    }
+                    SelectGraphicRendition::Underline
+                    | SelectGraphicRendition::DoublyUnderlined
+                    | SelectGraphicRendition::CurlyUnderline
+                    | SelectGraphicRendition::DottedUnderline
+                    | SelectGraphicRendition::DashedUnderline => self.sgr_underline = true,
+                    SelectGraphicRendition::NotUnderlined => self.sgr_underline = false,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Moves the overstrike formatting to the bold and underline given, nothing needs to change for a format that
+    /// the input has already set
+    fn set_formats(&mut self, bold: bool, underline: bool, result: &mut Text) {
+        let mut sgrs: Vec<SelectGraphicRendition> = Vec::new();
+        if bold != self.bold && !self.sgr_bold {
+            match bold {
+                true => sgrs.push(SelectGraphicRendition::Bold),
+                false => {
+                    // SGR 22 also takes away faint so it has to be put back
+                    sgrs.push(SelectGraphicRendition::NormalIntensity);
+                    if self.sgr_faint {
+                        sgrs.push(SelectGraphicRendition::Faint);
+                    }
+                }
+            }
+        }
+        if underline != self.underline && !self.sgr_underline {
+            sgrs.push(match underline {
+                true => SelectGraphicRendition::Underline,
+                false => SelectGraphicRendition::NotUnderlined,
+            });
+        }
+        if !sgrs.is_empty() {
+            result.text.push(TextElement::Marker(EscapeSequence::Fe(
+                FeEscapeSequence::ControlSequence(ControlSequence::SelectGraphicalRendition(sgrs)),
+            )));
+        }
+        self.bold = bold;
+        self.underline = underline;
+    }
+}
+
+fn push_char(c: char, result: &mut Text) {
+    match result.text.last_mut() {
+        Some(TextElement::Text(text)) => text.push(c),
+        _ => result.text.push(TextElement::Text(c.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Overstrike;
+    use crate::input_fmt::ansi::{
+        ControlSequence, EscapeSequence, FeEscapeSequence, SelectGraphicRendition, Text,
+        TextElement,
+    };
+
+    fn sgr(sgrs: Vec<SelectGraphicRendition>) -> TextElement {
+        TextElement::Marker(EscapeSequence::Fe(FeEscapeSequence::ControlSequence(
+            ControlSequence::SelectGraphicalRendition(sgrs),
+        )))
+    }
+
+    fn overstrike(chunks: &[&str]) -> Vec<TextElement> {
+        let mut overstrike = Overstrike::new();
+        let mut result: Vec<TextElement> = Vec::new();
+        let mut texts: Vec<Text> = chunks
+            .iter()
+            .map(|chunk| overstrike.convert(Text::from(chunk.to_string())))
+            .collect();
+        texts.push(overstrike.finish());
+        for text in texts {
+            for element in text.text {
+                match (result.last_mut(), element) {
+                    (Some(TextElement::Text(last)), TextElement::Text(text)) => {
+                        last.push_str(&text)
+                    }
+                    (_, element) => result.push(element),
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn overstrike_formats() {
+        assert_eq!(
+            overstrike(&["N\x08NA\x08AM\x08ME\x08E\n"]),
+            vec![
+                sgr(vec![SelectGraphicRendition::Bold]),
+                TextElement::Text("NAME".to_string()),
+                sgr(vec![SelectGraphicRendition::NormalIntensity]),
+                TextElement::Text("\n".to_string()),
+            ]
+        );
+        assert_eq!(
+            overstrike(&["see _\x08f_\x08i_", "\x08l_\x08e."]),
+            vec![
+                TextElement::Text("see ".to_string()),
+                sgr(vec![SelectGraphicRendition::Underline]),
+                TextElement::Text("file".to_string()),
+                sgr(vec![SelectGraphicRendition::NotUnderlined]),
+                TextElement::Text(".".to_string()),
+            ]
+        );
+        assert_eq!(
+            overstrike(&["_\x08X\x08X"]),
+            vec![
+                sgr(vec![
+                    SelectGraphicRendition::Bold,
+                    SelectGraphicRendition::Underline
+                ]),
+                TextElement::Text("X".to_string()),
+                sgr(vec![
+                    SelectGraphicRendition::NormalIntensity,
+                    SelectGraphicRendition::NotUnderlined
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn overstrike_keeps_sgr_formats() {
+        assert_eq!(
+            overstrike(&["\x1b[1;4mN\x08Na_\x08b\x1b[0m"]),
+            vec![
+                sgr(vec![
+                    SelectGraphicRendition::Bold,
+                    SelectGraphicRendition::Underline
+                ]),
+                TextElement::Text("Nab".to_string()),
+                sgr(vec![SelectGraphicRendition::Normal]),
+            ]
+        );
+        assert_eq!(
+            overstrike(&["\x1b[2mN\x08Na\x1b[22m"]),
+            vec![
+                sgr(vec![SelectGraphicRendition::Faint]),
+                sgr(vec![SelectGraphicRendition::Bold]),
+                TextElement::Text("N".to_string()),
+                sgr(vec![
+                    SelectGraphicRendition::NormalIntensity,
+                    SelectGraphicRendition::Faint
+                ]),
+                TextElement::Text("a".to_string()),
+                sgr(vec![SelectGraphicRendition::NormalIntensity]),
+            ]
+        );
+        // a marker inside of an overstruck run doesn't leave the overstrike formatting out of step
+        assert_eq!(
+            overstrike(&["N\x08N\x1b[31mA\x08A"]),
+            vec![
+                sgr(vec![SelectGraphicRendition::Bold]),
+                TextElement::Text("N".to_string()),
+                sgr(vec![SelectGraphicRendition::NormalIntensity]),
+                sgr(vec![SelectGraphicRendition::ForgroundColor(Some(
+                    crate::input_fmt::ansi::Color::Named(1)
+                ))]),
+                sgr(vec![SelectGraphicRendition::Bold]),
+                TextElement::Text("A".to_string()),
+                sgr(vec![SelectGraphicRendition::NormalIntensity]),
+            ]
+        );
+    }
+
+    #[test]
+    fn overstrike_leaves_other_backspaces() {
+        for input in ["spin |\x08/\x08-", "\x08start", "end\x08", "a\x08\x08b"] {
+            assert_eq!(
+                overstrike(&[input]),
+                vec![TextElement::Text(input.to_string())],
+                "{:?}",
+                input
+            );
+        }
+    }
+}
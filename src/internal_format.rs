@@ -1,7 +1,9 @@
 use crate::common;
 use crate::input_fmt::ansi;
+use crate::palette::Palette;
+use clap::Args;
 
-type Color = common::Color;
+type Color = common::AnsiColor;
 /// This represents the styling of text that we support as part of our output
 /// The idea is that all writers must be able to output these particular styles
 /// with out worrying about the other support by ANSI
@@ -10,83 +12,226 @@ pub enum Fromatting {
     Bold,
     Faint,
     Italic,
-    Underline(Option<Color>),
+    Underline(Underline),
+    /// The colour of the underline, when this is not set the underline is the same colour as the text
+    UnderlineColor(Option<Color>),
+    Overline,
     CrossedOut,
     ForgroundColor(Option<Color>),
     BackgroundColor(Option<Color>),
+    /// The forground and background colours are swapped
+    Reverse,
+    /// The text is hidden e.g. a password as it is typed
+    Conceal,
+    Blink(Blink),
+    /// The text is written in one of the alternate fonts, 1-9 are the alternate fonts and 10 is Fraktur
+    Font(u8),
+    /// The text has a box or circle drawn around it
+    Frame(Frame),
+    Script(Script),
+    ProportionalSpacing,
+    /// The text is a link to the URI given, this is unset with an empty URI
+    Hyperlink(String),
+}
+
+/// The style of line used to underline text
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum Underline {
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+/// The shape drawn around framed text
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum Frame {
+    Framed,
+    Encircled,
+}
+
+/// Text that is raised or lowered from the line
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum Script {
+    Superscript,
+    Subscript,
+}
+
+/// How fast blinking text blinks
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum Blink {
+    /// Less than 150 times a minute
+    Slow,
+    /// 150 times a minute or more
+    Rapid,
 }
 
 impl Fromatting {
     pub fn from_ansi(fe: &ansi::FeEscapeSequence) -> Option<Vec<common::Toggle<Fromatting>>> {
-        return match fe {
-            ansi::FeEscapeSequence::ControlSequence(cls) => match cls {
-                ansi::ControlSequence::SelectGraphicalRendition(sgrs) => {
-                    let mut results: Vec<common::Toggle<Fromatting>> = Vec::new();
-                    for sgr in sgrs {
-                        match sgr {
-                            ansi::SelectGraphicRendition::Bold => {
-                                results.push(common::Toggle::Set(Fromatting::Bold))
-                            }
-                            ansi::SelectGraphicRendition::Faint => {
-                                results.push(common::Toggle::Set(Fromatting::Faint))
-                            }
-                            ansi::SelectGraphicRendition::Underline => {
-                                results.push(common::Toggle::Set(Fromatting::Underline(None)))
-                            }
-                            ansi::SelectGraphicRendition::Italic => {
-                                results.push(common::Toggle::Set(Fromatting::Italic))
-                            }
-                            ansi::SelectGraphicRendition::CrossedOut => {
-                                results.push(common::Toggle::Set(Fromatting::CrossedOut))
-                            }
-                            ansi::SelectGraphicRendition::ForgroundColor(None) => results
-                                .push(common::Toggle::UnSet(Fromatting::ForgroundColor(None))),
-                            ansi::SelectGraphicRendition::BackgroundColor(None) => results
-                                .push(common::Toggle::UnSet(Fromatting::BackgroundColor(None))),
-                            ansi::SelectGraphicRendition::ForgroundColor(Some(color)) => results
-                                .push(common::Toggle::Set(Fromatting::ForgroundColor(Some(
-                                    color.clone(),
-                                )))),
-                            ansi::SelectGraphicRendition::BackgroundColor(Some(color)) => results
-                                .push(common::Toggle::Set(Fromatting::BackgroundColor(Some(
-                                    color.clone(),
-                                )))),
-                            ansi::SelectGraphicRendition::Normal => {
-                                results.push(common::Toggle::UnSet(Fromatting::Bold));
-                                results.push(common::Toggle::UnSet(Fromatting::Faint));
-                                results.push(common::Toggle::UnSet(Fromatting::Italic));
-                                results.push(common::Toggle::UnSet(Fromatting::Underline(None)));
-                                results.push(common::Toggle::UnSet(Fromatting::CrossedOut));
-                                results
-                                    .push(common::Toggle::UnSet(Fromatting::ForgroundColor(None)));
-                                results
-                                    .push(common::Toggle::UnSet(Fromatting::BackgroundColor(None)));
-                            }
-                            ansi::SelectGraphicRendition::NormalIntensity => {
-                                results.push(common::Toggle::UnSet(Fromatting::Bold));
-                                results.push(common::Toggle::UnSet(Fromatting::Faint));
-                            }
-                            ansi::SelectGraphicRendition::NotUnderlined => {
-                                results.push(common::Toggle::UnSet(Fromatting::Underline(None)))
-                            }
-                            ansi::SelectGraphicRendition::NotItalic => {
-                                results.push(common::Toggle::UnSet(Fromatting::Italic))
-                            }
-                            ansi::SelectGraphicRendition::NotCrossedOut => {
-                                results.push(common::Toggle::UnSet(Fromatting::CrossedOut))
-                            }
-                            ansi::SelectGraphicRendition::SetUnderlineColor(None) => {
-                                results.push(common::Toggle::UnSet(Fromatting::Underline(None)))
-                            }
-                            _ => {}
-                        };
-                    }
-                    return Some(results);
+        match fe {
+            ansi::FeEscapeSequence::ControlSequence(
+                ansi::ControlSequence::SelectGraphicalRendition(sgrs),
+            ) => {
+                let mut results: Vec<common::Toggle<Fromatting>> = Vec::new();
+                for sgr in sgrs {
+                    match sgr {
+                        ansi::SelectGraphicRendition::Bold => {
+                            results.push(common::Toggle::Set(Fromatting::Bold))
+                        }
+                        ansi::SelectGraphicRendition::Faint => {
+                            results.push(common::Toggle::Set(Fromatting::Faint))
+                        }
+                        ansi::SelectGraphicRendition::Underline => results.push(
+                            common::Toggle::Set(Fromatting::Underline(Underline::Single)),
+                        ),
+                        ansi::SelectGraphicRendition::DoublyUnderlined => results.push(
+                            common::Toggle::Set(Fromatting::Underline(Underline::Double)),
+                        ),
+                        ansi::SelectGraphicRendition::CurlyUnderline => results
+                            .push(common::Toggle::Set(Fromatting::Underline(Underline::Curly))),
+                        ansi::SelectGraphicRendition::DottedUnderline => results.push(
+                            common::Toggle::Set(Fromatting::Underline(Underline::Dotted)),
+                        ),
+                        ansi::SelectGraphicRendition::DashedUnderline => results.push(
+                            common::Toggle::Set(Fromatting::Underline(Underline::Dashed)),
+                        ),
+                        ansi::SelectGraphicRendition::Overlined => {
+                            results.push(common::Toggle::Set(Fromatting::Overline))
+                        }
+                        ansi::SelectGraphicRendition::NotOverlined => {
+                            results.push(common::Toggle::UnSet(Fromatting::Overline))
+                        }
+                        ansi::SelectGraphicRendition::Italic => {
+                            results.push(common::Toggle::Set(Fromatting::Italic))
+                        }
+                        ansi::SelectGraphicRendition::CrossedOut => {
+                            results.push(common::Toggle::Set(Fromatting::CrossedOut))
+                        }
+                        ansi::SelectGraphicRendition::Invert => {
+                            results.push(common::Toggle::Set(Fromatting::Reverse))
+                        }
+                        ansi::SelectGraphicRendition::NotReveresed => {
+                            results.push(common::Toggle::UnSet(Fromatting::Reverse))
+                        }
+                        ansi::SelectGraphicRendition::Conceal => {
+                            results.push(common::Toggle::Set(Fromatting::Conceal))
+                        }
+                        ansi::SelectGraphicRendition::Reveal => {
+                            results.push(common::Toggle::UnSet(Fromatting::Conceal))
+                        }
+                        ansi::SelectGraphicRendition::SlowBlink => {
+                            results.push(common::Toggle::Set(Fromatting::Blink(Blink::Slow)))
+                        }
+                        ansi::SelectGraphicRendition::RapidBlink => {
+                            results.push(common::Toggle::Set(Fromatting::Blink(Blink::Rapid)))
+                        }
+                        ansi::SelectGraphicRendition::NotBlinking => {
+                            results.push(common::Toggle::UnSet(Fromatting::Blink(Blink::Slow)))
+                        }
+                        ansi::SelectGraphicRendition::Font(0) => {
+                            results.push(common::Toggle::UnSet(Fromatting::Font(0)))
+                        }
+                        ansi::SelectGraphicRendition::Font(font) => {
+                            results.push(common::Toggle::Set(Fromatting::Font(*font)))
+                        }
+                        ansi::SelectGraphicRendition::Framed => {
+                            results.push(common::Toggle::Set(Fromatting::Frame(Frame::Framed)))
+                        }
+                        ansi::SelectGraphicRendition::Encircled => {
+                            results.push(common::Toggle::Set(Fromatting::Frame(Frame::Encircled)))
+                        }
+                        ansi::SelectGraphicRendition::NeitherFramedNorEncircled => {
+                            results.push(common::Toggle::UnSet(Fromatting::Frame(Frame::Framed)))
+                        }
+                        ansi::SelectGraphicRendition::Superscript => results
+                            .push(common::Toggle::Set(Fromatting::Script(Script::Superscript))),
+                        ansi::SelectGraphicRendition::Subscript => {
+                            results.push(common::Toggle::Set(Fromatting::Script(Script::Subscript)))
+                        }
+                        ansi::SelectGraphicRendition::NethirSuperOrSubScript => results.push(
+                            common::Toggle::UnSet(Fromatting::Script(Script::Superscript)),
+                        ),
+                        ansi::SelectGraphicRendition::ProportionalSpacing => {
+                            results.push(common::Toggle::Set(Fromatting::ProportionalSpacing))
+                        }
+                        ansi::SelectGraphicRendition::DisableProportionalSpacing => {
+                            results.push(common::Toggle::UnSet(Fromatting::ProportionalSpacing))
+                        }
+                        ansi::SelectGraphicRendition::ForgroundColor(None) => {
+                            results.push(common::Toggle::UnSet(Fromatting::ForgroundColor(None)))
+                        }
+                        ansi::SelectGraphicRendition::BackgroundColor(None) => {
+                            results.push(common::Toggle::UnSet(Fromatting::BackgroundColor(None)))
+                        }
+                        ansi::SelectGraphicRendition::ForgroundColor(Some(color)) => results.push(
+                            common::Toggle::Set(Fromatting::ForgroundColor(Some(*color))),
+                        ),
+                        ansi::SelectGraphicRendition::BackgroundColor(Some(color)) => results.push(
+                            common::Toggle::Set(Fromatting::BackgroundColor(Some(*color))),
+                        ),
+                        ansi::SelectGraphicRendition::Normal => {
+                            results.push(common::Toggle::UnSet(Fromatting::Bold));
+                            results.push(common::Toggle::UnSet(Fromatting::Faint));
+                            results.push(common::Toggle::UnSet(Fromatting::Italic));
+                            results.push(common::Toggle::UnSet(Fromatting::Underline(
+                                Underline::Single,
+                            )));
+                            results.push(common::Toggle::UnSet(Fromatting::UnderlineColor(None)));
+                            results.push(common::Toggle::UnSet(Fromatting::Overline));
+                            results.push(common::Toggle::UnSet(Fromatting::CrossedOut));
+                            results.push(common::Toggle::UnSet(Fromatting::ForgroundColor(None)));
+                            results.push(common::Toggle::UnSet(Fromatting::BackgroundColor(None)));
+                            results.push(common::Toggle::UnSet(Fromatting::Reverse));
+                            results.push(common::Toggle::UnSet(Fromatting::Conceal));
+                            results.push(common::Toggle::UnSet(Fromatting::Blink(Blink::Slow)));
+                            results.push(common::Toggle::UnSet(Fromatting::Font(0)));
+                            results.push(common::Toggle::UnSet(Fromatting::Frame(Frame::Framed)));
+                            results.push(common::Toggle::UnSet(Fromatting::Script(
+                                Script::Superscript,
+                            )));
+                            results.push(common::Toggle::UnSet(Fromatting::ProportionalSpacing));
+                        }
+                        ansi::SelectGraphicRendition::NormalIntensity => {
+                            results.push(common::Toggle::UnSet(Fromatting::Bold));
+                            results.push(common::Toggle::UnSet(Fromatting::Faint));
+                        }
+                        ansi::SelectGraphicRendition::NotUnderlined => results.push(
+                            common::Toggle::UnSet(Fromatting::Underline(Underline::Single)),
+                        ),
+                        ansi::SelectGraphicRendition::NotItalic => {
+                            results.push(common::Toggle::UnSet(Fromatting::Italic))
+                        }
+                        ansi::SelectGraphicRendition::NotCrossedOut => {
+                            results.push(common::Toggle::UnSet(Fromatting::CrossedOut))
+                        }
+                        ansi::SelectGraphicRendition::SetUnderlineColor(None) => {
+                            results.push(common::Toggle::UnSet(Fromatting::UnderlineColor(None)))
+                        }
+                        ansi::SelectGraphicRendition::SetUnderlineColor(Some(color)) => results
+                            .push(common::Toggle::Set(Fromatting::UnderlineColor(Some(
+                                *color,
+                            )))),
+                        _ => {}
+                    };
                 }
-                _ => None,
-            },
+                Some(results)
+            }
+            ansi::FeEscapeSequence::OperatingSystemCommand(
+                ansi::OperatingSystemCommand::Hyperlink { uri, .. },
+            ) => {
+                if uri.is_empty() {
+                    Some(vec![common::Toggle::UnSet(Fromatting::Hyperlink(
+                        String::new(),
+                    ))])
+                } else {
+                    Some(vec![common::Toggle::Set(Fromatting::Hyperlink(
+                        uri.clone(),
+                    ))])
+                }
+            }
             _ => None,
-        };
+        }
     }
 }
 
@@ -96,23 +241,293 @@ pub type Text = common::Text<common::Toggle<Fromatting>>;
 
 impl Text {
     pub fn from_ansi(text: ansi::Text) -> Text {
+        Converter::new().convert(text)
+    }
+
+    /// Resolves the markers into runs of text along with the full style each run is shown with, runs that are
+    /// next to each other always have different styles.
+    pub fn runs(&self) -> Vec<(Style, String)> {
+        self.runs_from(&mut Style::default())
+    }
+
+    /// Resolves the markers into runs of text the same way as `runs` starting from the style given, the style is
+    /// left as it is at the end of the text so that the next part of a larger text can carry on from it.
+    pub fn runs_from(&self, style: &mut Style) -> Vec<(Style, String)> {
+        let mut runs: Vec<(Style, String)> = Vec::new();
+        for element in self.text.iter() {
+            match element {
+                TextElement::Text(text) if text.is_empty() => {}
+                TextElement::Text(text) => match runs.last_mut() {
+                    Some((last, string)) if last == style => string.push_str(text),
+                    _ => runs.push((style.clone(), text.clone())),
+                },
+                TextElement::Marker(toggle) => style.apply(toggle),
+            }
+        }
+        runs
+    }
+}
+
+/// The full state of the formatting that applies to a piece of text.
+///
+/// Unlike a list of Fromatting each attribute can only have one value so setting a format replaces the previous
+/// value of that attribute e.g. setting a new forground color replaces the old one.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Style {
+    pub bold: bool,
+    pub faint: bool,
+    pub italic: bool,
+    pub underline: Option<Underline>,
+    pub underline_color: Option<Color>,
+    pub overline: bool,
+    pub crossed_out: bool,
+    pub forground_color: Option<Color>,
+    pub background_color: Option<Color>,
+    pub reverse: bool,
+    pub conceal: bool,
+    pub blink: Option<Blink>,
+    pub font: Option<u8>,
+    pub frame: Option<Frame>,
+    pub script: Option<Script>,
+    pub proportional_spacing: bool,
+    pub hyperlink: Option<String>,
+}
+
+impl Style {
+    /// Applies the marker given to the style
+    pub fn apply(&mut self, toggle: &common::Toggle<Fromatting>) {
+        let (fmt, set) = match toggle {
+            common::Toggle::Set(fmt) => (fmt, true),
+            common::Toggle::UnSet(fmt) => (fmt, false),
+        };
+        match fmt {
+            Fromatting::Bold => self.bold = set,
+            Fromatting::Faint => self.faint = set,
+            Fromatting::Italic => self.italic = set,
+            Fromatting::Underline(underline) => self.underline = set.then_some(*underline),
+            Fromatting::UnderlineColor(color) => {
+                self.underline_color = if set { *color } else { None }
+            }
+            Fromatting::Overline => self.overline = set,
+            Fromatting::CrossedOut => self.crossed_out = set,
+            Fromatting::ForgroundColor(color) => {
+                self.forground_color = if set { *color } else { None }
+            }
+            Fromatting::BackgroundColor(color) => {
+                self.background_color = if set { *color } else { None }
+            }
+            Fromatting::Reverse => self.reverse = set,
+            Fromatting::Conceal => self.conceal = set,
+            Fromatting::Blink(blink) => self.blink = set.then_some(*blink),
+            Fromatting::Font(font) => self.font = set.then_some(*font),
+            Fromatting::Frame(frame) => self.frame = set.then_some(*frame),
+            Fromatting::Script(script) => self.script = set.then_some(*script),
+            Fromatting::ProportionalSpacing => self.proportional_spacing = set,
+            Fromatting::Hyperlink(uri) => {
+                self.hyperlink = (set && !uri.is_empty()).then(|| uri.clone())
+            }
+        }
+    }
+}
+
+/// Converts ansi::Text into our internal Text while keeping track of which formats are active.
+///
+/// This allows a single piece of text to be converted in multiple parts e.g. line by line while the formatting
+/// that was active at the end of one part is carried over to the next.
+#[derive(Debug, Default)]
+pub struct Converter {
+    active: Vec<Fromatting>,
+}
+
+impl Converter {
+    pub fn new() -> Converter {
+        Converter { active: Vec::new() }
+    }
+
+    /// Returns the formats that are currently active
+    pub fn active(&self) -> &[Fromatting] {
+        &self.active
+    }
+
+    /// Converts the next part of the text, the formats that are active from the previous parts are re-applied
+    /// at the start of the text returned.
+    pub fn convert(&mut self, text: ansi::Text) -> Text {
         let mut new_impl = Text { text: Vec::new() };
-        for element in text.text.iter() {
+        for fmt in self.active.iter() {
+            new_impl
+                .text
+                .push(TextElement::Marker(common::Toggle::Set(fmt.clone())));
+        }
+        for element in text.text {
             match element {
                 ansi::TextElement::Text(string) => {
-                    new_impl.text.push(TextElement::Text(string.clone()));
+                    new_impl.text.push(TextElement::Text(string));
                 }
-                ansi::TextElement::Marker(marker) => match Fromatting::from_ansi(marker) {
-                    Some(fmts) => {
+                ansi::TextElement::Marker(ansi::EscapeSequence::Fe(marker)) => {
+                    if let Some(fmts) = Fromatting::from_ansi(&marker) {
                         for fmt in fmts {
+                            apply(&mut self.active, &fmt);
                             new_impl.text.push(TextElement::Marker(fmt))
                         }
                     }
-                    None => {}
-                },
+                }
+                ansi::TextElement::Marker(_) => {}
+            }
+        }
+        new_impl
+    }
+}
+
+/// The options that change how the formats are shown so that the output matches what was seen in a terminal
+#[derive(Args, Clone, Debug, Default)]
+#[group(id = "ConversionOptions")]
+pub struct Options {
+    /// Show bold text in one of the 8 base colours (SGR 30-37) in the
+    /// bright varient of the colour as many terminals do e.g. `ESC[1;30m` is
+    /// shown as grey.
+    #[arg(long)]
+    pub bold_is_bright: bool,
+    /// Show faint text by dimming its colour towards the background rather
+    /// than with a lighter font.
+    #[arg(long)]
+    pub faint_dims_color: bool,
+    /// Show reversed text (SGR 7) by swapping its forground and background
+    /// colours, the default colours of the palette are used for any colour
+    /// that is not set.
+    #[arg(long)]
+    pub reverse_colors: bool,
+}
+
+impl Options {
+    /// Returns true if any of the options change how the text is shown
+    pub fn any(&self) -> bool {
+        self.bold_is_bright || self.faint_dims_color || self.reverse_colors
+    }
+}
+
+/// Rewrites the formats of converted text to show them the way a terminal would, see Options for what is
+/// changed.
+///
+/// The formats that were given are tracked separately from the formats that are shown so that e.g. the bright
+/// colour used for bold text goes back to the normal colour once the text is no longer bold.
+#[derive(Debug)]
+pub struct Quirks {
+    options: Options,
+    palette: Palette,
+    active: Vec<Fromatting>,
+    shown: Vec<Fromatting>,
+}
+
+impl Quirks {
+    pub fn new(options: &Options, palette: Palette) -> Quirks {
+        Quirks {
+            options: options.clone(),
+            palette,
+            active: Vec::new(),
+            shown: Vec::new(),
+        }
+    }
+
+    /// Converts the next part of the text, formats carry over from the previous parts.
+    ///
+    /// The markers between two pieces of text are applied together so only the overall change is written out.
+    pub fn convert(&mut self, text: Text) -> Text {
+        let mut result = Text { text: Vec::new() };
+        for element in text.text {
+            match element {
+                TextElement::Text(string) => {
+                    self.change_formats(&mut result);
+                    result.text.push(TextElement::Text(string));
+                }
+                TextElement::Marker(toggle) => apply(&mut self.active, &toggle),
+            }
+        }
+        self.change_formats(&mut result);
+        result
+    }
+
+    /// Adds the markers needed to go from the formats that are shown to the formats shown for the active formats
+    fn change_formats(&mut self, result: &mut Text) {
+        let shown = self.show();
+        for fmt in self.shown.iter().rev() {
+            if !shown.contains(fmt) {
+                result
+                    .text
+                    .push(TextElement::Marker(common::Toggle::UnSet(fmt.clone())));
+            }
+        }
+        for fmt in shown.iter() {
+            if !self.shown.contains(fmt) {
+                result
+                    .text
+                    .push(TextElement::Marker(common::Toggle::Set(fmt.clone())));
             }
         }
-        return new_impl;
+        self.shown = shown;
+    }
+
+    /// Works out the formats that are shown for the active formats
+    fn show(&self) -> Vec<Fromatting> {
+        let mut shown = self.active.clone();
+        if self.options.bold_is_bright && shown.contains(&Fromatting::Bold) {
+            for fmt in shown.iter_mut() {
+                if let Fromatting::ForgroundColor(Some(Color::Named(index @ 0..=7))) = fmt {
+                    *index += 8;
+                }
+            }
+        }
+        if self.options.reverse_colors && shown.contains(&Fromatting::Reverse) {
+            let foreground = Quirks::take_color(&mut shown, &Fromatting::ForgroundColor(None));
+            let background = Quirks::take_color(&mut shown, &Fromatting::BackgroundColor(None));
+            shown.retain(|fmt| *fmt != Fromatting::Reverse);
+            shown.push(Fromatting::ForgroundColor(Some(
+                background.unwrap_or(Color::Rgb(self.palette.background)),
+            )));
+            shown.push(Fromatting::BackgroundColor(Some(
+                foreground.unwrap_or(Color::Rgb(self.palette.foreground)),
+            )));
+        }
+        if self.options.faint_dims_color && shown.contains(&Fromatting::Faint) {
+            let foreground = match Quirks::take_color(&mut shown, &Fromatting::ForgroundColor(None))
+            {
+                Some(color) => self.palette.resolve(color),
+                None => self.palette.foreground,
+            };
+            let background = self.palette.background;
+            let dim = |fg: u8, bg: u8| ((u16::from(fg) + u16::from(bg)) / 2) as u8;
+            shown.retain(|fmt| *fmt != Fromatting::Faint);
+            shown.push(Fromatting::ForgroundColor(Some(Color::Rgb(
+                common::Color {
+                    red: dim(foreground.red, background.red),
+                    green: dim(foreground.green, background.green),
+                    blue: dim(foreground.blue, background.blue),
+                },
+            ))));
+        }
+        shown
+    }
+
+    /// Removes the colour of the same kind as `kind` from the formats returning the colour if it was set
+    fn take_color(formats: &mut Vec<Fromatting>, kind: &Fromatting) -> Option<Color> {
+        let index = formats
+            .iter()
+            .position(|fmt| std::mem::discriminant(fmt) == std::mem::discriminant(kind))?;
+        match formats.remove(index) {
+            Fromatting::ForgroundColor(color) | Fromatting::BackgroundColor(color) => color,
+            _ => None,
+        }
+    }
+}
+
+/// Updates the active formats, setting a format replaces any active format of the same kind e.g. a new forground
+/// color replaces the old one.
+pub fn apply(active: &mut Vec<Fromatting>, toggle: &common::Toggle<Fromatting>) {
+    let fmt = match toggle {
+        common::Toggle::Set(fmt) | common::Toggle::UnSet(fmt) => fmt,
+    };
+    active.retain(|active| std::mem::discriminant(active) != std::mem::discriminant(fmt));
+    if let common::Toggle::Set(fmt) = toggle {
+        active.push(fmt.clone());
     }
 }
 
@@ -126,12 +541,48 @@ mod test {
         let test_cases = vec![
             (ansi::FeEscapeSequence::SingleShiftTwo, None),
             (ansi::FeEscapeSequence::SingleShiftThree, None),
-            (ansi::FeEscapeSequence::DeviceControlString, None),
-            (ansi::FeEscapeSequence::OperatingSystemCommand, None),
+            (
+                ansi::FeEscapeSequence::DeviceControlString("q#0;2;0;0;0".to_string()),
+                None,
+            ),
+            (
+                ansi::FeEscapeSequence::OperatingSystemCommand(
+                    ansi::OperatingSystemCommand::Other("0;title".to_string()),
+                ),
+                None,
+            ),
+            (
+                ansi::FeEscapeSequence::OperatingSystemCommand(
+                    ansi::OperatingSystemCommand::Hyperlink {
+                        params: "id=1".to_string(),
+                        uri: "https://example.com".to_string(),
+                    },
+                ),
+                Some(vec![crate::common::Toggle::Set(
+                    super::Fromatting::Hyperlink("https://example.com".to_string()),
+                )]),
+            ),
+            (
+                ansi::FeEscapeSequence::OperatingSystemCommand(
+                    ansi::OperatingSystemCommand::Hyperlink {
+                        params: String::new(),
+                        uri: String::new(),
+                    },
+                ),
+                Some(vec![crate::common::Toggle::UnSet(
+                    super::Fromatting::Hyperlink(String::new()),
+                )]),
+            ),
             (ansi::FeEscapeSequence::StringTerminator, None),
-            (ansi::FeEscapeSequence::StartOfString, None),
-            (ansi::FeEscapeSequence::PrivacyMessage, None),
-            (ansi::FeEscapeSequence::ApplicationProgramCommand, None),
+            (ansi::FeEscapeSequence::StartOfString(String::new()), None),
+            (
+                ansi::FeEscapeSequence::PrivacyMessage("message".to_string()),
+                None,
+            ),
+            (
+                ansi::FeEscapeSequence::ApplicationProgramCommand("Gf=100".to_string()),
+                None,
+            ),
             (
                 ansi::FeEscapeSequence::ControlSequence(
                     ansi::ControlSequence::SelectGraphicalRendition(vec![
@@ -140,6 +591,84 @@ mod test {
                 ),
                 Some(vec![crate::common::Toggle::Set(super::Fromatting::Italic)]),
             ),
+            (
+                ansi::FeEscapeSequence::ControlSequence(
+                    ansi::ControlSequence::SelectGraphicalRendition(vec![
+                        ansi::SelectGraphicRendition::Invert,
+                        ansi::SelectGraphicRendition::Conceal,
+                        ansi::SelectGraphicRendition::RapidBlink,
+                        ansi::SelectGraphicRendition::NotBlinking,
+                        ansi::SelectGraphicRendition::Reveal,
+                        ansi::SelectGraphicRendition::NotReveresed,
+                    ]),
+                ),
+                Some(vec![
+                    crate::common::Toggle::Set(super::Fromatting::Reverse),
+                    crate::common::Toggle::Set(super::Fromatting::Conceal),
+                    crate::common::Toggle::Set(super::Fromatting::Blink(super::Blink::Rapid)),
+                    crate::common::Toggle::UnSet(super::Fromatting::Blink(super::Blink::Slow)),
+                    crate::common::Toggle::UnSet(super::Fromatting::Conceal),
+                    crate::common::Toggle::UnSet(super::Fromatting::Reverse),
+                ]),
+            ),
+            (
+                ansi::FeEscapeSequence::ControlSequence(
+                    ansi::ControlSequence::SelectGraphicalRendition(vec![
+                        ansi::SelectGraphicRendition::DoublyUnderlined,
+                        ansi::SelectGraphicRendition::DashedUnderline,
+                        ansi::SelectGraphicRendition::SetUnderlineColor(Some(
+                            common::AnsiColor::Indexed(200),
+                        )),
+                        ansi::SelectGraphicRendition::Overlined,
+                        ansi::SelectGraphicRendition::NotOverlined,
+                        ansi::SelectGraphicRendition::SetUnderlineColor(None),
+                        ansi::SelectGraphicRendition::NotUnderlined,
+                    ]),
+                ),
+                Some(vec![
+                    crate::common::Toggle::Set(super::Fromatting::Underline(
+                        super::Underline::Double,
+                    )),
+                    crate::common::Toggle::Set(super::Fromatting::Underline(
+                        super::Underline::Dashed,
+                    )),
+                    crate::common::Toggle::Set(super::Fromatting::UnderlineColor(Some(
+                        common::AnsiColor::Indexed(200),
+                    ))),
+                    crate::common::Toggle::Set(super::Fromatting::Overline),
+                    crate::common::Toggle::UnSet(super::Fromatting::Overline),
+                    crate::common::Toggle::UnSet(super::Fromatting::UnderlineColor(None)),
+                    crate::common::Toggle::UnSet(super::Fromatting::Underline(
+                        super::Underline::Single,
+                    )),
+                ]),
+            ),
+            (
+                ansi::FeEscapeSequence::ControlSequence(
+                    ansi::ControlSequence::SelectGraphicalRendition(vec![
+                        ansi::SelectGraphicRendition::Font(10),
+                        ansi::SelectGraphicRendition::Font(0),
+                        ansi::SelectGraphicRendition::Encircled,
+                        ansi::SelectGraphicRendition::NeitherFramedNorEncircled,
+                        ansi::SelectGraphicRendition::Subscript,
+                        ansi::SelectGraphicRendition::NethirSuperOrSubScript,
+                        ansi::SelectGraphicRendition::ProportionalSpacing,
+                        ansi::SelectGraphicRendition::DisableProportionalSpacing,
+                    ]),
+                ),
+                Some(vec![
+                    crate::common::Toggle::Set(super::Fromatting::Font(10)),
+                    crate::common::Toggle::UnSet(super::Fromatting::Font(0)),
+                    crate::common::Toggle::Set(super::Fromatting::Frame(super::Frame::Encircled)),
+                    crate::common::Toggle::UnSet(super::Fromatting::Frame(super::Frame::Framed)),
+                    crate::common::Toggle::Set(super::Fromatting::Script(super::Script::Subscript)),
+                    crate::common::Toggle::UnSet(super::Fromatting::Script(
+                        super::Script::Superscript,
+                    )),
+                    crate::common::Toggle::Set(super::Fromatting::ProportionalSpacing),
+                    crate::common::Toggle::UnSet(super::Fromatting::ProportionalSpacing),
+                ]),
+            ),
             (
                 ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::CursorDown(3)),
                 None,
@@ -219,39 +748,21 @@ mod test {
                 None,
             ),
             (
-                ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::VT220Cursor),
-                None,
-            ),
-            (
-                ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::HideCursor),
-                None,
-            ),
-            (
-                ansi::FeEscapeSequence::ControlSequence(
-                    ansi::ControlSequence::EnableReportingFocus,
-                ),
-                None,
-            ),
-            (
-                ansi::FeEscapeSequence::ControlSequence(
-                    ansi::ControlSequence::DisableReportingFocus,
-                ),
-                None,
-            ),
-            (
-                ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::EnableAltScreenBuf),
-                None,
-            ),
-            (
-                ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::DisableAltScreenBuf),
-                None,
-            ),
-            (
-                ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::BracketPasteMode),
+                ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::SetMode(
+                    ansi::Modes {
+                        private_marker: Some('?'),
+                        modes: vec![25, 1049],
+                    },
+                )),
                 None,
             ),
             (
-                ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::NoBracketPasteMode),
+                ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::ResetMode(
+                    ansi::Modes {
+                        private_marker: Some('?'),
+                        modes: vec![2004],
+                    },
+                )),
                 None,
             ),
         ];
@@ -267,23 +778,36 @@ mod test {
             ansi::Text {
                 text: vec![
                     ansi::TextElement::Text("This".to_string()),
-                    ansi::TextElement::Marker(ansi::FeEscapeSequence::SingleShiftTwo),
-                    ansi::TextElement::Marker(ansi::FeEscapeSequence::ControlSequence(
-                        ansi::ControlSequence::SelectGraphicalRendition(vec![
-                            ansi::SelectGraphicRendition::ForgroundColor(Some(common::red())),
-                        ]),
+                    ansi::TextElement::Marker(ansi::EscapeSequence::Fe(
+                        ansi::FeEscapeSequence::SingleShiftTwo,
+                    )),
+                    ansi::TextElement::Marker(ansi::EscapeSequence::Fp(
+                        ansi::FpEscapeSequence::SaveCursor,
+                    )),
+                    ansi::TextElement::Marker(ansi::EscapeSequence::Fe(
+                        ansi::FeEscapeSequence::ControlSequence(
+                            ansi::ControlSequence::SelectGraphicalRendition(vec![
+                                ansi::SelectGraphicRendition::ForgroundColor(Some(
+                                    common::AnsiColor::Named(1),
+                                )),
+                            ]),
+                        ),
                     )),
                     ansi::TextElement::Text("is a".to_string()),
-                    ansi::TextElement::Marker(ansi::FeEscapeSequence::ControlSequence(
-                        ansi::ControlSequence::SelectGraphicalRendition(vec![
-                            ansi::SelectGraphicRendition::Bold,
-                        ]),
+                    ansi::TextElement::Marker(ansi::EscapeSequence::Fe(
+                        ansi::FeEscapeSequence::ControlSequence(
+                            ansi::ControlSequence::SelectGraphicalRendition(vec![
+                                ansi::SelectGraphicRendition::Bold,
+                            ]),
+                        ),
                     )),
                     ansi::TextElement::Text("Test".to_string()),
-                    ansi::TextElement::Marker(ansi::FeEscapeSequence::ControlSequence(
-                        ansi::ControlSequence::SelectGraphicalRendition(vec![
-                            ansi::SelectGraphicRendition::Normal,
-                        ]),
+                    ansi::TextElement::Marker(ansi::EscapeSequence::Fe(
+                        ansi::FeEscapeSequence::ControlSequence(
+                            ansi::ControlSequence::SelectGraphicalRendition(vec![
+                                ansi::SelectGraphicRendition::Normal,
+                            ]),
+                        ),
                     )),
                 ],
             },
@@ -291,7 +815,7 @@ mod test {
                 text: vec![
                     super::TextElement::Text("This".to_string()),
                     super::TextElement::Marker(crate::common::Toggle::Set(
-                        super::Fromatting::ForgroundColor(Some(crate::common::red())),
+                        super::Fromatting::ForgroundColor(Some(crate::common::AnsiColor::Named(1))),
                     )),
                     super::TextElement::Text("is a".to_string()),
                     super::TextElement::Marker(crate::common::Toggle::Set(super::Fromatting::Bold)),
@@ -306,7 +830,13 @@ mod test {
                         super::Fromatting::Italic,
                     )),
                     super::TextElement::Marker(crate::common::Toggle::UnSet(
-                        super::Fromatting::Underline(None),
+                        super::Fromatting::Underline(super::Underline::Single),
+                    )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::UnderlineColor(None),
+                    )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::Overline,
                     )),
                     super::TextElement::Marker(crate::common::Toggle::UnSet(
                         super::Fromatting::CrossedOut,
@@ -317,6 +847,27 @@ mod test {
                     super::TextElement::Marker(crate::common::Toggle::UnSet(
                         super::Fromatting::BackgroundColor(None),
                     )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::Reverse,
+                    )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::Conceal,
+                    )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::Blink(super::Blink::Slow),
+                    )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::Font(0),
+                    )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::Frame(super::Frame::Framed),
+                    )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::Script(super::Script::Superscript),
+                    )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::ProportionalSpacing,
+                    )),
                 ],
             },
         )];
@@ -325,4 +876,212 @@ mod test {
             assert_eq!(super::Text::from_ansi(input), expected_output)
         }
     }
+
+    #[test]
+    pub fn converter_carries_formats() {
+        let mut converter = super::Converter::new();
+        let first = converter.convert(ansi::Text::from("\x1b[1;31mone\x1b[32m".to_string()));
+        assert_eq!(
+            first.text.last(),
+            Some(&super::TextElement::Marker(crate::common::Toggle::Set(
+                super::Fromatting::ForgroundColor(Some(crate::common::AnsiColor::Named(2)))
+            )))
+        );
+        assert_eq!(
+            converter.active(),
+            &[
+                super::Fromatting::Bold,
+                super::Fromatting::ForgroundColor(Some(crate::common::AnsiColor::Named(2))),
+            ]
+        );
+        let second = converter.convert(ansi::Text::from("two\x1b[22m".to_string()));
+        assert_eq!(
+            second,
+            super::Text {
+                text: vec![
+                    super::TextElement::Marker(crate::common::Toggle::Set(super::Fromatting::Bold)),
+                    super::TextElement::Marker(crate::common::Toggle::Set(
+                        super::Fromatting::ForgroundColor(Some(crate::common::AnsiColor::Named(2)))
+                    )),
+                    super::TextElement::Text("two".to_string()),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::Bold,
+                    )),
+                    super::TextElement::Marker(crate::common::Toggle::UnSet(
+                        super::Fromatting::Faint,
+                    )),
+                ],
+            }
+        );
+        assert_eq!(
+            converter.active(),
+            &[super::Fromatting::ForgroundColor(Some(
+                crate::common::AnsiColor::Named(2)
+            ))]
+        );
+    }
+
+    fn quirks(options: super::Options, input: &str) -> Vec<super::TextElement> {
+        let text = super::Text::from_ansi(ansi::Text::from(input.to_string()));
+        super::Quirks::new(&options, crate::palette::Palette::default())
+            .convert(text)
+            .text
+    }
+
+    #[test]
+    pub fn quirks_bold_is_bright() {
+        let options = super::Options {
+            bold_is_bright: true,
+            faint_dims_color: false,
+            reverse_colors: false,
+        };
+        assert_eq!(
+            quirks(options, "\x1b[30ma\x1b[1mb\x1b[22mc\x1b[1;38;5;1md"),
+            vec![
+                super::TextElement::Marker(common::Toggle::Set(super::Fromatting::ForgroundColor(
+                    Some(common::AnsiColor::Named(0))
+                ))),
+                super::TextElement::Text("a".to_string()),
+                super::TextElement::Marker(common::Toggle::UnSet(
+                    super::Fromatting::ForgroundColor(Some(common::AnsiColor::Named(0)))
+                )),
+                super::TextElement::Marker(common::Toggle::Set(super::Fromatting::ForgroundColor(
+                    Some(common::AnsiColor::Named(8))
+                ))),
+                super::TextElement::Marker(common::Toggle::Set(super::Fromatting::Bold)),
+                super::TextElement::Text("b".to_string()),
+                super::TextElement::Marker(common::Toggle::UnSet(super::Fromatting::Bold)),
+                super::TextElement::Marker(common::Toggle::UnSet(
+                    super::Fromatting::ForgroundColor(Some(common::AnsiColor::Named(8)))
+                )),
+                super::TextElement::Marker(common::Toggle::Set(super::Fromatting::ForgroundColor(
+                    Some(common::AnsiColor::Named(0))
+                ))),
+                super::TextElement::Text("c".to_string()),
+                super::TextElement::Marker(common::Toggle::UnSet(
+                    super::Fromatting::ForgroundColor(Some(common::AnsiColor::Named(0)))
+                )),
+                super::TextElement::Marker(common::Toggle::Set(super::Fromatting::Bold)),
+                super::TextElement::Marker(common::Toggle::Set(super::Fromatting::ForgroundColor(
+                    Some(common::AnsiColor::Indexed(1))
+                ))),
+                super::TextElement::Text("d".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn quirks_reverse_and_faint() {
+        let options = super::Options {
+            bold_is_bright: false,
+            faint_dims_color: true,
+            reverse_colors: true,
+        };
+        let palette = crate::palette::Palette::default();
+        assert_eq!(
+            quirks(options.clone(), "\x1b[7;41ma\x1b[27mb"),
+            vec![
+                super::TextElement::Marker(common::Toggle::Set(super::Fromatting::ForgroundColor(
+                    Some(common::AnsiColor::Named(1))
+                ))),
+                super::TextElement::Marker(common::Toggle::Set(
+                    super::Fromatting::BackgroundColor(Some(common::AnsiColor::Rgb(
+                        palette.foreground
+                    )))
+                )),
+                super::TextElement::Text("a".to_string()),
+                super::TextElement::Marker(common::Toggle::UnSet(
+                    super::Fromatting::BackgroundColor(Some(common::AnsiColor::Rgb(
+                        palette.foreground
+                    )))
+                )),
+                super::TextElement::Marker(common::Toggle::UnSet(
+                    super::Fromatting::ForgroundColor(Some(common::AnsiColor::Named(1)))
+                )),
+                super::TextElement::Marker(common::Toggle::Set(
+                    super::Fromatting::BackgroundColor(Some(common::AnsiColor::Named(1)))
+                )),
+                super::TextElement::Text("b".to_string()),
+            ]
+        );
+        assert_eq!(
+            quirks(options, "\x1b[2;91mdim"),
+            vec![
+                super::TextElement::Marker(common::Toggle::Set(super::Fromatting::ForgroundColor(
+                    Some(common::AnsiColor::Rgb(common::Color {
+                        red: 127,
+                        green: 0,
+                        blue: 0
+                    }))
+                ))),
+                super::TextElement::Text("dim".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn text_runs() {
+        let text = super::Text::from_ansi(ansi::Text::from(
+            "a\x1b[31mb\x1b[32mc\x1b[1md\x1b[39me\x1b[22m\x1b[0mf\x1b[1m\x1b[22mg".to_string(),
+        ));
+        let green = super::Style {
+            forground_color: Some(common::AnsiColor::Named(2)),
+            ..super::Style::default()
+        };
+        assert_eq!(
+            text.runs(),
+            vec![
+                (super::Style::default(), "a".to_string()),
+                (
+                    super::Style {
+                        forground_color: Some(common::AnsiColor::Named(1)),
+                        ..super::Style::default()
+                    },
+                    "b".to_string()
+                ),
+                (green.clone(), "c".to_string()),
+                (
+                    super::Style {
+                        bold: true,
+                        ..green
+                    },
+                    "d".to_string()
+                ),
+                (
+                    super::Style {
+                        bold: true,
+                        ..super::Style::default()
+                    },
+                    "e".to_string()
+                ),
+                (super::Style::default(), "fg".to_string()),
+            ]
+        );
+        let mut style = super::Style::default();
+        let first = super::Text::from_ansi(ansi::Text::from("a\x1b[1mb\x1b[3m".to_string()));
+        let second = super::Text::from_ansi(ansi::Text::from("c\x1b[0md".to_string()));
+        let bold = super::Style {
+            bold: true,
+            ..super::Style::default()
+        };
+        assert_eq!(
+            first.runs_from(&mut style),
+            vec![
+                (super::Style::default(), "a".to_string()),
+                (bold.clone(), "b".to_string()),
+            ]
+        );
+        assert_eq!(
+            style,
+            super::Style {
+                italic: true,
+                ..bold.clone()
+            }
+        );
+        assert_eq!(
+            second.runs_from(&mut style)[1],
+            (super::Style::default(), "d".to_string())
+        );
+        assert_eq!(style, super::Style::default());
+    }
 }
@@ -3,16 +3,21 @@
 use clap::Parser;
 use std::{
     fs::File,
-    io::{self, BufRead, Write},
+    io::{self, BufRead, Read, Write},
     path::PathBuf,
-    sync::mpsc::channel,
+    sync::{mpsc::channel, Arc},
 };
 use threadpool::ThreadPool;
 
+/// The number of bytes that are read from a file at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
 pub mod common;
 pub mod input_fmt;
 pub mod internal_format;
 pub mod output_fmt;
+pub mod palette;
+pub mod terminal;
 
 #[derive(Parser, Debug)]
 struct App {
@@ -33,113 +38,284 @@ struct App {
     ///amount of threads.
     #[arg(short, long, default_value_t = 1)]
     threads: usize,
+    /// This specifies what to do with input that is not valid UTF-8, it can be
+    /// replaced with U+FFFD, passed through as is or written out as \xNN.
+    #[arg(long, value_enum, default_value_t = input_fmt::ansi::InvalidUtf8::Replace)]
+    invalid_utf8: input_fmt::ansi::InvalidUtf8,
+    /// This specifies the character encoding of the input.
+    #[arg(long, value_enum, default_value_t = input_fmt::ansi::Encoding::Utf8)]
+    encoding: input_fmt::ansi::Encoding,
+    /// Treat the 8-bit C1 control characters (U+0080 to U+009F) as escape
+    /// sequence introducers e.g. U+009B as `ESC [`.
+    #[arg(long)]
+    c1_controls: bool,
+    /// Read the textual forms of the escape character (`\033`, `\x1b`,
+    /// `\u001b`, `\e` and `^[`) as an escape character when they start a
+    /// sequence, this is useful for JSON logs and console exports.
+    #[arg(long)]
+    literal_escapes: bool,
+    #[command(flatten)]
+    output_options: output_fmt::Options,
+    #[command(flatten)]
+    terminal_options: terminal::Options,
+    #[command(flatten)]
+    conversion_options: internal_format::Options,
 }
 
 pub fn parse_text(format: &output_fmt::OutputFormat, string: String) -> Result<String, String> {
-    let mut ansi_text = input_fmt::ansi::Text::new();
-    ansi_text.read(string);
-    match output_fmt::from(format.clone(), internal_format::Text::from_ansi(ansi_text)) {
-        Some(formater) => {
-            return Ok(formater.to_string());
+    let mut parser = input_fmt::ansi::Parser::new();
+    let mut processor = terminal::Processor::new(&terminal::Options::default());
+    let mut text = read_chunk(&mut parser, &mut processor, string.as_bytes());
+    text.text
+        .append(&mut read_finish(&mut parser, &mut processor).text);
+    match output_fmt::from(*format, text) {
+        Some(formater) => Ok(formater.to_string()),
+        None => Err("Failed to find a writer for the given output format.".to_string()),
+    }
+}
+
+/// Converts a whole file a chunk at a time handing each piece of the body to `output` as soon as it has been
+/// formatted, only the body is written so that the outputs of multiple files can be joined together into one
+/// document.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_file<R: Read, F: FnMut(Vec<u8>) -> Result<(), String>>(
+    format: &output_fmt::OutputFormat,
+    options: &output_fmt::Options,
+    stylesheet: &Arc<output_fmt::html::Stylesheet>,
+    terminal_options: &terminal::Options,
+    conversion_options: &internal_format::Options,
+    mut parser: input_fmt::ansi::Parser,
+    mut file: R,
+    mut output: F,
+) -> Result<(), String> {
+    let mut processor =
+        terminal::Processor::new(terminal_options).with_quirks(conversion_options, options.palette);
+    let mut formatter = output_fmt::new(*format, options, stylesheet);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let text = match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(length) => read_chunk(&mut parser, &mut processor, &chunk[..length]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        let mut buffer: Vec<u8> = Vec::new();
+        if let Err(e) = formatter.body(&mut buffer, &text) {
+            return Err(e.to_string());
         }
-        None => return Err("Failed to find a writer for the given output format.".to_string()),
+        output(buffer)?;
+    }
+    let mut buffer: Vec<u8> = Vec::new();
+    if let Err(e) = formatter.body(&mut buffer, &read_finish(&mut parser, &mut processor)) {
+        return Err(e.to_string());
+    }
+    if let Err(e) = formatter.close(&mut buffer) {
+        return Err(e.to_string());
     }
+    output(buffer)
+}
+
+/// Converts a chunk of a larger input, the parser given holds on to any escape sequence or character that is
+/// cut off at the end of the chunk so that it can be completed by the next one and the processor carries the
+/// formatting that is active at the end of the chunk over to the next one.
+pub fn read_chunk(
+    parser: &mut input_fmt::ansi::Parser,
+    processor: &mut terminal::Processor,
+    chunk: &[u8],
+) -> internal_format::Text {
+    let mut ansi_text = input_fmt::ansi::Text::new();
+    parser.read_bytes(chunk, &mut ansi_text);
+    processor.process(ansi_text)
+}
+
+/// Converts anything the parser and processor were still holding on to once the end of the input has been
+/// reached
+pub fn read_finish(
+    parser: &mut input_fmt::ansi::Parser,
+    processor: &mut terminal::Processor,
+) -> internal_format::Text {
+    let mut ansi_text = input_fmt::ansi::Text::new();
+    parser.finish(&mut ansi_text);
+    processor.finish(ansi_text)
 }
 
-pub fn run_async(
+/// Converts the files on a pool of threads writing their output to the stream in the order the files were
+/// given. The output of a file is written as it is produced while it is the earliest file that hasn't been
+/// written yet, the output of the files after it is held on to until it is their turn. The stylesheet is the one
+/// used by the stream's formatter so that it has the classes used by every file.
+#[allow(clippy::too_many_arguments)]
+pub fn run_async<W: Write>(
+    stream: &mut output_fmt::Stream<W>,
     paths: Vec<PathBuf>,
     threads: usize,
     format: output_fmt::OutputFormat,
-) -> Result<Vec<String>, String> {
+    options: &output_fmt::Options,
+    stylesheet: &Arc<output_fmt::html::Stylesheet>,
+    terminal_options: &terminal::Options,
+    conversion_options: &internal_format::Options,
+    parser: input_fmt::ansi::Parser,
+) -> Result<(), String> {
     let pool = ThreadPool::new(threads);
-    let mut results: Vec<String> = Vec::new();
-    let tp_fmt = format.clone();
+    let tp_fmt = format;
     let (tx, rx) = channel();
-    for path in paths.iter() {
+    for (index, path) in paths.iter().enumerate() {
         let tp_path = path.clone();
         let tp_tx = tx.clone();
+        let tp_parser = parser.clone();
+        let tp_options = options.clone();
+        let tp_stylesheet = Arc::clone(stylesheet);
+        let tp_terminal_options = terminal_options.clone();
+        let tp_conversion_options = conversion_options.clone();
         pool.execute(move || {
-            let file = File::open(tp_path).unwrap();
-            let reader = io::BufReader::new(file);
-            match parse_text(&tp_fmt, std::io::read_to_string(reader).unwrap()) {
-                Ok(output_text) => {
-                    tp_tx.send(Ok(output_text)).unwrap();
-                }
-                Err(e) => {
-                    tp_tx.send(Err(e)).unwrap();
-                }
-            }
+            let result = match File::open(&tp_path) {
+                Ok(file) => parse_file(
+                    &tp_fmt,
+                    &tp_options,
+                    &tp_stylesheet,
+                    &tp_terminal_options,
+                    &tp_conversion_options,
+                    tp_parser,
+                    file,
+                    |output| {
+                        tp_tx
+                            .send((index, Ok(Some(output))))
+                            .map_err(|e| e.to_string())
+                    },
+                ),
+                Err(e) => Err(e.to_string()),
+            };
+            // a None marks the end of the file's output
+            let result = result
+                .map(|_| None)
+                .map_err(|e| format!("{}: {}", tp_path.display(), e));
+            // the receiver is only gone if another file has already failed
+            let _ = tp_tx.send((index, result));
         });
     }
+    drop(tx);
 
-    for result in rx.iter().take(paths.len()) {
+    let mut pending: Vec<Vec<Vec<u8>>> = vec![Vec::new(); paths.len()];
+    let mut finished = vec![false; paths.len()];
+    let mut next = 0;
+    for (index, result) in rx.iter() {
         match result {
-            Ok(output_txt) => {
-                results.push(output_txt);
+            Ok(Some(output)) if index == next => {
+                if let Err(e) = stream.write_formatted(&output) {
+                    return Err(e.to_string());
+                }
             }
+            Ok(Some(output)) => pending[index].push(output),
+            Ok(None) => finished[index] = true,
             Err(e) => {
                 return Err(e);
             }
         }
+        while next < paths.len() && finished[next] {
+            next += 1;
+            if next < paths.len() {
+                for output in std::mem::take(&mut pending[next]) {
+                    if let Err(e) = stream.write_formatted(&output) {
+                        return Err(e.to_string());
+                    }
+                }
+            }
+        }
+    }
+    if next < paths.len() {
+        return Err(format!("{}: conversion failed", paths[next].display()));
     }
 
-    return Ok(results);
+    Ok(())
 }
 
-pub fn run_stream(output: Option<String>, format: output_fmt::OutputFormat) -> Result<(), String> {
-    let mut out_writer = match &output {
-        Some(x) => Box::new(File::create(x).unwrap()) as Box<dyn Write>,
-        None => Box::new(io::stdout()) as Box<dyn Write>,
-    };
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        match line {
-            Ok(txt) => match parse_text(&format, txt + "\n") {
-                Ok(output_text) => {
-                    out_writer.write(output_text.as_bytes()).unwrap();
+/// Converts standard in line by line, when the input is being run through a terminal nothing is written until
+/// the end of the input as any line on the screen may still change.
+pub fn run_stream<W: Write>(
+    stream: &mut output_fmt::Stream<W>,
+    mut parser: input_fmt::ansi::Parser,
+    terminal_options: &terminal::Options,
+    conversion_options: &internal_format::Options,
+    palette: palette::Palette,
+) -> Result<(), String> {
+    let mut processor =
+        terminal::Processor::new(terminal_options).with_quirks(conversion_options, palette);
+    let mut stdin = io::stdin().lock();
+    let mut line: Vec<u8> = Vec::new();
+    loop {
+        line.clear();
+        match stdin.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                let text = read_chunk(&mut parser, &mut processor, &line);
+                if let Err(e) = stream.write(&text) {
+                    return Err(e.to_string());
                 }
-                Err(e) => return Err(e),
-            },
+            }
             Err(e) => {
                 return Err(e.to_string());
             }
         }
     }
-    return Ok(());
+    match stream.write(&read_finish(&mut parser, &mut processor)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 fn main() -> Result<(), String> {
     let app = App::parse();
-    let mut out_writer = match &app.output {
-        Some(x) => Box::new(File::create(x).unwrap()) as Box<dyn Write>,
+    let out_writer = match &app.output {
+        Some(x) => match File::create(x) {
+            Ok(file) => Box::new(file) as Box<dyn Write>,
+            Err(e) => return Err(e.to_string()),
+        },
         None => Box::new(io::stdout()) as Box<dyn Write>,
     };
-    if app.paths.len() > 0 {
-        match run_async(app.paths, app.threads, app.format) {
-            Ok(output_text) => {
-                for text in output_text.iter() {
-                    if let Err(e) = out_writer.write(text.as_bytes()) {
-                        return Err(e.to_string());
-                    }
-                }
-            }
-            Err(e) => {
-                return Err(e);
-            }
-        }
+    let stylesheet = Arc::new(output_fmt::html::Stylesheet::default());
+    let mut stream = output_fmt::Stream::new(
+        output_fmt::new(app.format, &app.output_options, &stylesheet),
+        io::BufWriter::new(out_writer),
+    );
+    let parser = input_fmt::ansi::Parser::new()
+        .with_invalid_utf8(app.invalid_utf8)
+        .with_encoding(app.encoding)
+        .with_c1_controls(app.c1_controls)
+        .with_literal_escapes(app.literal_escapes);
+    if !app.paths.is_empty() {
+        run_async(
+            &mut stream,
+            app.paths,
+            app.threads,
+            app.format,
+            &app.output_options,
+            &stylesheet,
+            &app.terminal_options,
+            &app.conversion_options,
+            parser,
+        )?;
     } else {
-        match run_stream(app.output, app.format) {
-            Ok(_) => {}
-            Err(e) => return Err(e),
-        }
+        run_stream(
+            &mut stream,
+            parser,
+            &app.terminal_options,
+            &app.conversion_options,
+            app.output_options.palette,
+        )?;
     }
 
-    return Ok(());
+    match stream.finish() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{output_fmt, parse_text, App};
+    use crate::{
+        input_fmt, internal_format, output_fmt, parse_text, read_chunk, run_async, terminal, App,
+        CHUNK_SIZE,
+    };
+    use clap::Parser;
 
     #[test]
     pub fn app_parse_text() {
@@ -152,6 +328,7 @@ mod test {
                         output: Some("test.txt".to_string()),
                         paths: vec![],
                         threads: 1,
+                        ..App::parse_from(["ansi_fmt"])
                     },
                 ),
                 "Test",
@@ -164,9 +341,10 @@ mod test {
                         output: Some("test.html".to_string()),
                         paths: vec![],
                         threads: 1,
+                        ..App::parse_from(["ansi_fmt"])
                     },
                 ),
-                "<span style=\"font-weight:bold;color:#0080;\">Test</span>",
+                "<span style=\"font-weight:bold;color:#008000;\">Test</span>",
             ),
             (
                 (
@@ -176,6 +354,7 @@ mod test {
                         output: Some("test.txt".to_string()),
                         paths: vec![],
                         threads: 1,
+                        ..App::parse_from(["ansi_fmt"])
                     },
                 ),
                 "Test",
@@ -195,4 +374,90 @@ mod test {
             }
         }
     }
+
+    #[test]
+    pub fn app_overstrike() {
+        assert!(App::parse_from(["ansi_fmt"]).terminal_options.overstrike);
+        assert!(
+            !App::parse_from(["ansi_fmt", "--no-overstrike"])
+                .terminal_options
+                .overstrike
+        );
+        assert_eq!(
+            parse_text(&output_fmt::OutputFormat::Html, "N\x08NAME".to_string()).unwrap(),
+            "<span style=\"font-weight:bold;\">N</span>AME"
+        );
+    }
+
+    #[test]
+    pub fn app_read_chunk() {
+        let mut parser = input_fmt::ansi::Parser::new();
+        let mut processor = terminal::Processor::new(&terminal::Options::default());
+        let results: Vec<String> = ["\x1b[1mfirst\n", "second\x1b[0m\n", "third\n"]
+            .iter()
+            .map(|line| {
+                output_fmt::from(
+                    output_fmt::OutputFormat::Html,
+                    read_chunk(&mut parser, &mut processor, line.as_bytes()),
+                )
+                .unwrap()
+                .to_string()
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                "<span style=\"font-weight:bold;\">first\n</span>".to_string(),
+                "<span style=\"font-weight:bold;\">second</span>\n".to_string(),
+                "third\n".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn app_run_async_in_order() {
+        let directory =
+            std::env::temp_dir().join(format!("ansi_fmt_run_async_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // the first file is the largest so that it finishes last and spans more than one chunk
+        let contents = [
+            format!("\x1b[1m{}\x1b[0m\n", "a".repeat(CHUNK_SIZE * 3)),
+            "\x1b[32msecond\x1b[0m\n".to_string(),
+            "third\n".to_string(),
+            "fourth\n".to_string(),
+        ];
+        let paths: Vec<std::path::PathBuf> = contents
+            .iter()
+            .enumerate()
+            .map(|(index, content)| {
+                let path = directory.join(format!("{}.txt", index));
+                std::fs::write(&path, content).unwrap();
+                path
+            })
+            .collect();
+        let options = output_fmt::Options::default();
+        let stylesheet = Default::default();
+        let mut stream = output_fmt::Stream::new(
+            output_fmt::new(output_fmt::OutputFormat::Text, &options, &stylesheet),
+            Vec::new(),
+        );
+        run_async(
+            &mut stream,
+            paths,
+            4,
+            output_fmt::OutputFormat::Text,
+            &options,
+            &stylesheet,
+            &terminal::Options::default(),
+            &internal_format::Options::default(),
+            input_fmt::ansi::Parser::new(),
+        )
+        .unwrap();
+        let result = String::from_utf8(stream.finish().unwrap()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            result,
+            format!("{}\nsecond\nthird\nfourth\n", "a".repeat(CHUNK_SIZE * 3))
+        );
+    }
 }
@@ -1,146 +1,570 @@
-use crate::common;
-use crate::internal_format;
+use crate::common::{self, AnsiColor};
+use crate::internal_format::{self, Blink, Frame, Script, Style, Underline};
 use crate::output_fmt;
+use crate::palette::Palette;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
+/// How the HTML is written out
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug, Default)]
+pub enum HtmlMode {
+    /// Just the formatted text so that it can be embedded in another page
+    #[default]
+    Fragment,
+    /// A complete HTML5 page with the text inside of a `<pre>`
+    Document,
+}
+
+/// The CSS rules for the classes used when writing HTML with classes.
+///
+/// The writers that make up one output share a stylesheet e.g. the writers used for each file on other threads
+/// add the classes they use to the stylesheet of the writer that writes it out, see [`HtmlWriter::with_stylesheet`].
+#[derive(Debug, Default)]
+pub struct Stylesheet {
+    rules: Mutex<BTreeMap<String, String>>,
+}
+
+impl Stylesheet {
+    fn add(&self, class: &str, css: &str) {
+        self.rules
+            .lock()
+            .unwrap()
+            .entry(class.to_string())
+            .or_insert_with(|| css.to_string());
+    }
+
+    /// Returns true if no classes have been used
+    pub fn is_empty(&self) -> bool {
+        self.rules.lock().unwrap().is_empty()
+    }
+
+    /// Writes out a rule for each class used so far, the class names start with the prefix given
+    pub fn write(&self, out: &mut dyn Write, prefix: &str) -> io::Result<()> {
+        let rules = self.rules.lock().unwrap();
+        if rules.keys().any(|class| class.starts_with("blink")) {
+            writeln!(out, "{}", blink_keyframes(prefix))?;
+        }
+        for (class, css) in rules.iter() {
+            writeln!(out, ".{}{}{{{}}}", prefix, class, css)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes out the text as HTML with each run of formatted text wrapped in a styled span
+#[derive(Clone, Debug, Default)]
 pub struct HtmlWriter {
-    text: internal_format::Text,
+    style: Style,
+    hyperlink: Option<String>,
+    palette: Palette,
+    fonts: Vec<(u8, String)>,
+    mode: HtmlMode,
+    title: Option<String>,
+    css_classes: bool,
+    class_prefix: String,
+    stylesheet_file: Option<PathBuf>,
+    stylesheet_href: Option<String>,
+    stylesheet: Arc<Stylesheet>,
+    reveal: bool,
+    blink_defined: bool,
 }
 
 impl HtmlWriter {
-    pub fn from_text(txt: internal_format::Text) -> HtmlWriter {
-        HtmlWriter { text: txt }
+    pub fn new() -> HtmlWriter {
+        HtmlWriter {
+            style: Style::default(),
+            hyperlink: None,
+            palette: Palette::default(),
+            fonts: Vec::new(),
+            mode: HtmlMode::Fragment,
+            title: None,
+            css_classes: false,
+            class_prefix: DEFAULT_CLASS_PREFIX.to_string(),
+            stylesheet_file: None,
+            stylesheet_href: None,
+            stylesheet: Arc::default(),
+            reveal: false,
+            blink_defined: false,
+        }
     }
-}
 
-fn remove_fmt(
-    fmt: &internal_format::Fromatting,
-    current_active_formats: &mut Vec<internal_format::Fromatting>,
-) {
-    let mut i: usize = 0;
-    for cur_fmt in current_active_formats.iter() {
-        if fmt == cur_fmt {
-            current_active_formats.remove(i);
-            break;
+    pub fn from_options(options: &output_fmt::Options) -> HtmlWriter {
+        HtmlWriter {
+            palette: options.palette,
+            fonts: options.fonts.clone(),
+            mode: options.html_mode,
+            title: options.title.clone(),
+            css_classes: options.css_classes,
+            class_prefix: options.class_prefix.clone(),
+            stylesheet_file: options.stylesheet_file.clone(),
+            stylesheet_href: options.stylesheet_href.clone(),
+            reveal: options.reveal,
+            ..HtmlWriter::new()
         }
-        i = i + 1;
     }
-}
 
-fn contains_fmt(
-    fmt: &internal_format::Fromatting,
-    current_active_formats: &Vec<internal_format::Fromatting>,
-) -> bool {
-    for format in current_active_formats.iter() {
-        match format {
-            internal_format::Fromatting::BackgroundColor(_) => {
-                if fmt.clone() == internal_format::Fromatting::BackgroundColor(None) {
-                    return true;
-                }
+    /// Adds the classes used to the stylesheet given rather than one of the writer's own
+    pub fn with_stylesheet(mut self, stylesheet: Arc<Stylesheet>) -> HtmlWriter {
+        self.stylesheet = stylesheet;
+        self
+    }
+
+    pub fn from_text(txt: internal_format::Text) -> output_fmt::Rendered<HtmlWriter> {
+        output_fmt::Rendered::new(HtmlWriter::new(), txt)
+    }
+
+    /// Opens a link to the URI given, links with a scheme that is not allowed are left out and the text is
+    /// written without a link.
+    fn open_hyperlink(&mut self, out: &mut dyn Write, uri: &str) -> io::Result<()> {
+        if self.hyperlink.as_deref() == Some(uri) {
+            return Ok(());
+        }
+        self.close_hyperlink(out)?;
+        if !allowed_uri(uri) {
+            return Ok(());
+        }
+        write!(out, "<a href=\"{}\">", escape_attribute(uri))?;
+        self.hyperlink = Some(uri.to_string());
+        Ok(())
+    }
+
+    fn close_hyperlink(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if self.hyperlink.take().is_some() {
+            out.write_all(b"</a>")?;
+        }
+        Ok(())
+    }
+
+    /// Writes out a run of text in the style given
+    fn run(&mut self, out: &mut dyn Write, style: &Style, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        match style.hyperlink.clone() {
+            Some(uri) => self.open_hyperlink(out, &uri)?,
+            None => self.close_hyperlink(out)?,
+        }
+        if style.blink.is_some() && !self.blink_defined && !self.css_classes {
+            write!(
+                out,
+                "<style>{}</style>",
+                blink_keyframes(&self.class_prefix)
+            )?;
+            self.blink_defined = true;
+        }
+        // concealed text is left out of the output so that it can not be seen by selecting it either
+        let text = match style.conceal && !self.reveal {
+            true => output_fmt::conceal(text),
+            false => escape_text(text),
+        };
+        let rules = style_rules(style, &self.palette, &self.fonts, &self.class_prefix);
+        let script = match style.script {
+            Some(Script::Superscript) => Some("sup"),
+            Some(Script::Subscript) => Some("sub"),
+            None => None,
+        };
+        if let Some(tag) = script {
+            write!(out, "<{}>", tag)?;
+        }
+        if rules.is_empty() {
+            out.write_all(text.as_bytes())?;
+        } else if self.css_classes {
+            let mut classes: Vec<String> = Vec::new();
+            for (class, css) in rules.iter() {
+                self.stylesheet.add(class, css);
+                classes.push(format!("{}{}", self.class_prefix, class));
             }
-            internal_format::Fromatting::ForgroundColor(_) => {
-                if fmt.clone() == internal_format::Fromatting::ForgroundColor(None) {
-                    return true;
-                }
+            write!(
+                out,
+                "<span class=\"{}\">{}</span>",
+                escape_attribute(&classes.join(" ")),
+                text
+            )?;
+        } else {
+            let css: String = rules.into_iter().map(|(_, css)| css).collect();
+            write!(
+                out,
+                "<span style=\"{}\">{}</span>",
+                escape_attribute(&css),
+                text
+            )?;
+        }
+        if let Some(tag) = script {
+            write!(out, "</{}>", tag)?;
+        }
+        Ok(())
+    }
+
+    /// The CSS rules for the page as a whole when writing a document
+    fn page_css(&self) -> String {
+        let hex = |c: common::Color| format!("#{:02x}{:02x}{:02x}", c.red, c.green, c.blue);
+        format!(
+            "body{{color:{};background-color:{};}}pre{{font-family:{};}}",
+            hex(self.palette.foreground),
+            hex(self.palette.background),
+            MONOSPACE_FONTS,
+        )
+    }
+
+    /// Writes out the start of a document up to the opening of the `<pre>` that holds the text.
+    ///
+    /// When using a stylesheet file the CSS for the page is part of it so that there are no inline styles,
+    /// otherwise it is written in a `<style>` block. The rules for the classes are only known once all of the
+    /// text has been written so an embedded stylesheet comes after the `<pre>`.
+    fn write_head(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        write!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n",
+            escape_text(self.title.as_deref().unwrap_or(DEFAULT_TITLE)),
+        )?;
+        match (self.css_classes, &self.stylesheet_file) {
+            (true, Some(path)) => {
+                let href = match &self.stylesheet_href {
+                    Some(href) => href.clone(),
+                    None => path_to_href(path),
+                };
+                writeln!(
+                    out,
+                    "<link rel=\"stylesheet\" href=\"{}\">",
+                    escape_attribute(&href)
+                )?
             }
-            internal_format::Fromatting::Underline(_) => {
-                if fmt.clone() == internal_format::Fromatting::Underline(None) {
-                    return true;
+            _ => writeln!(out, "<style>{}</style>", self.page_css())?,
+        }
+        out.write_all(b"</head>\n<body>\n<pre>\n")
+    }
+
+    /// Writes out the stylesheet for the classes used, either to the stylesheet file or embedded in the output,
+    /// a document's stylesheet file also has the CSS for the page.
+    fn write_stylesheet(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        match &self.stylesheet_file {
+            Some(path) => {
+                let mut file = File::create(path)?;
+                if self.mode == HtmlMode::Document {
+                    writeln!(file, "{}", self.page_css())?;
                 }
+                self.stylesheet.write(&mut file, &self.class_prefix)
             }
-            _ => {
-                if fmt.clone() == format.clone() {
-                    return true;
-                }
+            None if self.stylesheet.is_empty() => Ok(()),
+            None => {
+                out.write_all(b"<style>\n")?;
+                self.stylesheet.write(out, &self.class_prefix)?;
+                out.write_all(b"</style>\n")
             }
         }
     }
-    return false;
 }
 
-fn formats_to_styles(current_active_formats: &Vec<internal_format::Fromatting>) -> String {
-    let mut output_buffer = String::new();
-    for fmt in current_active_formats.iter() {
-        match fmt {
-            internal_format::Fromatting::Bold => output_buffer.push_str("font-weight:bold"),
-            internal_format::Fromatting::Faint => output_buffer.push_str("font-weight:lighter"),
-            internal_format::Fromatting::Italic => output_buffer.push_str("font-style:italic"),
-            internal_format::Fromatting::Underline(Some(c)) => output_buffer.push_str(
-                format!(
-                    "font-decoration:line-through;text-decoration-color:#{:x}{:x}{:x}",
-                    c.red(),
-                    c.blue(),
-                    c.green()
-                )
-                .as_str(),
-            ),
-            internal_format::Fromatting::Underline(None) => {
-                output_buffer.push_str("font-decoration:line-through")
+/// Turns the path of the stylesheet file into the URL used to link to it, relative paths stay relative to the
+/// page and absolute paths become `file:` URLs.
+fn path_to_href(path: &Path) -> String {
+    let mut href = String::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                href.push_str("file:///");
+                href.push_str(&prefix.as_os_str().to_string_lossy().replace('\\', "/"));
             }
-            internal_format::Fromatting::CrossedOut => {
-                output_buffer.push_str("font-decoration:line-through")
+            Component::RootDir if href.is_empty() => href.push_str("file:///"),
+            Component::RootDir => href.push('/'),
+            Component::CurDir => {}
+            Component::ParentDir => href.push_str("../"),
+            Component::Normal(name) => {
+                href.push_str(&percent_encode(&name.to_string_lossy()));
+                href.push('/');
             }
-            internal_format::Fromatting::ForgroundColor(None) => {
-                output_buffer.push_str("color:inherit")
+        }
+    }
+    href.trim_end_matches('/').to_string()
+}
+
+/// Percent encodes everything but the characters that can be used as is in a segment of a URL path
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
             }
-            internal_format::Fromatting::BackgroundColor(None) => {
-                output_buffer.push_str("background-color:inherit")
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The prefix of the class names when one is not given with `--class-prefix`
+pub const DEFAULT_CLASS_PREFIX: &str = "ansi-";
+
+/// The title of the page when one is not given with `--title`
+static DEFAULT_TITLE: &str = "ansi_fmt";
+
+/// The fonts used for the text of a document, the first one that is installed is used
+static MONOSPACE_FONTS: &str =
+    "ui-monospace, 'Cascadia Mono', 'DejaVu Sans Mono', Menlo, Consolas, 'Liberation Mono', monospace";
+
+/// The font family used for Fraktur text when one is not given with `--font`
+static FRAKTUR_FAMILY: &str = "UnifrakturMaguntia, fantasy";
+
+/// The animation used for blinking text, this is written out before the first blinking text or as part of the
+/// stylesheet. It is named with the class prefix so that it does not clash with the rest of the page.
+fn blink_keyframes(prefix: &str) -> String {
+    format!("@keyframes {}blink{{50%{{opacity:0}}}}", prefix)
+}
+
+/// The URI schemes that hyperlinks can use, anything else e.g. `javascript:` could run code when the link is
+/// clicked.
+const ALLOWED_SCHEMES: [&str; 5] = ["http", "https", "ftp", "file", "mailto"];
+
+/// Returns true if the URI starts with one of the allowed schemes.
+///
+/// URIs without a scheme are not allowed either as browsers strip some characters before reading the scheme so
+/// anything that does not strictly start with a scheme can not be trusted.
+fn allowed_uri(uri: &str) -> bool {
+    match uri.split_once(':') {
+        Some((scheme, _)) => ALLOWED_SCHEMES
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(scheme)),
+        None => false,
+    }
+}
+
+/// Escapes the text given so that it is shown as is rather than being read as HTML
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes the value given so that it can be safely placed inside of a double quoted attribute
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Removes the characters that could be used to end a CSS declaration or the `<style>` block it is in from a
+/// value that was given on the command line
+fn css_value(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !matches!(c, '{' | '}' | ';' | '<' | '>' | '\\'))
+        .collect()
+}
+
+/// Converts a style into the CSS rules used to show it, each rule is the name of the class used for it along
+/// with its CSS declarations.
+///
+/// Reversed text swaps the forground and background colours, the default colours of the palette are used for
+/// whichever of them is not set.
+///
+/// CSS only has one style and colour for all of the lines drawn on a piece of text so the style and colour of the
+/// underline are also used for any overline or line through it.
+///
+/// The alternate fonts use the font family given for them in `fonts`, the last one given wins. Proportional
+/// spacing is not shown as the text is already shown in whatever font the page uses.
+fn style_rules(
+    style: &Style,
+    palette: &Palette,
+    fonts: &[(u8, String)],
+    prefix: &str,
+) -> Vec<(String, String)> {
+    let hex = |c: common::Color| format!("#{:02x}{:02x}{:02x}", c.red, c.green, c.blue);
+    let color = |color: &AnsiColor| hex(palette.resolve(*color));
+    let mut rules: Vec<(String, String)> = Vec::new();
+    let mut rule = |class: String, css: String| rules.push((class, css));
+    if style.bold {
+        rule("bold".to_string(), "font-weight:bold;".to_string());
+    }
+    if style.faint {
+        rule("faint".to_string(), "font-weight:lighter;".to_string());
+    }
+    if style.italic {
+        rule("italic".to_string(), "font-style:italic;".to_string());
+    }
+    if let Some(font) = style.font {
+        let family = match fonts.iter().rev().find(|(slot, _)| *slot == font) {
+            Some((_, family)) => Some(family.as_str()),
+            None if font == 10 => Some(FRAKTUR_FAMILY),
+            None => None,
+        };
+        if let Some(family) = family {
+            rule(
+                format!("font-{}", font),
+                format!("font-family:{};", css_value(family)),
+            );
+        }
+    }
+    let mut lines: Vec<&str> = Vec::new();
+    if style.underline.is_some() {
+        lines.push("underline");
+    }
+    if style.overline {
+        lines.push("overline");
+    }
+    if style.crossed_out {
+        lines.push("line-through");
+    }
+    if !lines.is_empty() {
+        rule(
+            lines.join("-"),
+            format!("text-decoration-line:{};", lines.join(" ")),
+        );
+    }
+    let decoration_style = match style.underline {
+        Some(Underline::Double) => Some("double"),
+        Some(Underline::Curly) => Some("wavy"),
+        Some(Underline::Dotted) => Some("dotted"),
+        Some(Underline::Dashed) => Some("dashed"),
+        Some(Underline::Single) | None => None,
+    };
+    if let Some(decoration_style) = decoration_style {
+        rule(
+            format!("decoration-{}", decoration_style),
+            format!("text-decoration-style:{};", decoration_style),
+        );
+    }
+    if let (Some(_), Some(c)) = (&style.underline, &style.underline_color) {
+        rule(
+            format!("decoration-{}", color_class(c)),
+            format!("text-decoration-color:{};", color(c)),
+        );
+    }
+    // with `--reverse-colors` the colours have already been swapped and the text is no longer marked as reversed
+    let (forground, background) = match style.reverse {
+        false => (style.forground_color, style.background_color),
+        true => (
+            Some(
+                style
+                    .background_color
+                    .unwrap_or(AnsiColor::Rgb(palette.background)),
+            ),
+            Some(
+                style
+                    .forground_color
+                    .unwrap_or(AnsiColor::Rgb(palette.foreground)),
+            ),
+        ),
+    };
+    if let Some(c) = &forground {
+        rule(
+            format!("fg-{}", color_class(c)),
+            format!("color:{};", color(c)),
+        );
+    }
+    if let Some(c) = &background {
+        rule(
+            format!("bg-{}", color_class(c)),
+            format!("background-color:{};", color(c)),
+        );
+    }
+    match style.frame {
+        Some(Frame::Framed) => rule("framed".to_string(), "border:1px solid;".to_string()),
+        Some(Frame::Encircled) => rule(
+            "encircled".to_string(),
+            "border:1px solid;border-radius:1em;".to_string(),
+        ),
+        None => {}
+    }
+    match style.blink {
+        Some(Blink::Slow) => rule(
+            "blink".to_string(),
+            format!("animation:{}blink 1s step-end infinite;", prefix),
+        ),
+        Some(Blink::Rapid) => rule(
+            "blink-rapid".to_string(),
+            format!("animation:{}blink 0.4s step-end infinite;", prefix),
+        ),
+        None => {}
+    }
+    rules
+}
+
+/// Returns the part of a class name used for a colour, the colours of the palette use their index e.g. `1` and
+/// 24 bit colours use their hex value e.g. `rgb-ff0000`.
+fn color_class(color: &AnsiColor) -> String {
+    match color {
+        AnsiColor::Named(index) | AnsiColor::Indexed(index) => index.to_string(),
+        AnsiColor::Rgb(c) => format!("rgb-{:02x}{:02x}{:02x}", c.red, c.green, c.blue),
+    }
+}
+
+impl output_fmt::Formatter for HtmlWriter {
+    fn begin(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        match self.mode {
+            HtmlMode::Document => self.write_head(out),
+            HtmlMode::Fragment => Ok(()),
+        }
+    }
+
+    fn element(
+        &mut self,
+        out: &mut dyn Write,
+        element: &internal_format::TextElement,
+    ) -> io::Result<()> {
+        match element {
+            internal_format::TextElement::Text(t) => {
+                let style = self.style.clone();
+                self.run(out, &style, t)
             }
-            internal_format::Fromatting::ForgroundColor(Some(c)) => output_buffer
-                .push_str(format!("color:#{:x}{:x}{:x}", c.red(), c.blue(), c.green()).as_str()),
-            internal_format::Fromatting::BackgroundColor(Some(c)) => output_buffer.push_str(
-                format!(
-                    "background-color:#{:x}{:x}{:x}",
-                    c.red(),
-                    c.blue(),
-                    c.green()
-                )
-                .as_str(),
-            ),
-        };
-        output_buffer.push(';');
-    }
-    return output_buffer;
-}
-
-impl std::fmt::Display for HtmlWriter {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut current_active_fmt: Vec<internal_format::Fromatting> = Vec::new();
-        for element in self.text.text.iter() {
-            match element {
-                internal_format::TextElement::Text(t) => {
-                    let fmt_string: String;
-                    if current_active_fmt.len() > 0 {
-                        fmt_string = format!(
-                            "<span style=\"{}\">{}</span>",
-                            formats_to_styles(&current_active_fmt),
-                            t
-                        )
-                    } else {
-                        fmt_string = t.clone();
-                    }
-                    if let Err(e) = fmt.write_str(&fmt_string.as_str()) {
-                        return Err(e);
-                    }
-                }
-                internal_format::TextElement::Marker(m) => match m {
-                    common::Toggle::Set(f) => current_active_fmt.push(f.clone()),
-                    common::Toggle::UnSet(f) => {
-                        if contains_fmt(f, &current_active_fmt) {
-                            remove_fmt(f, &mut current_active_fmt);
-                        }
-                    }
-                },
+            internal_format::TextElement::Marker(m) => {
+                self.style.apply(m);
+                Ok(())
             }
         }
+    }
+
+    fn body(&mut self, out: &mut dyn Write, text: &internal_format::Text) -> io::Result<()> {
+        for (style, t) in text.runs_from(&mut self.style) {
+            self.run(out, &style, &t)?;
+        }
         Ok(())
     }
-}
 
-impl output_fmt::Formatter for HtmlWriter {}
+    fn close(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.close_hyperlink(out)
+    }
+
+    fn end(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.close(out)?;
+        if self.mode == HtmlMode::Document {
+            out.write_all(b"</pre>\n")?;
+        }
+        if self.css_classes {
+            self.write_stylesheet(out)?;
+        }
+        if self.mode == HtmlMode::Document {
+            out.write_all(b"</body>\n</html>\n")?;
+        }
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod test {
-    use crate::{internal_format, internal_format::TextElement, output_fmt::html::HtmlWriter};
+    use crate::{
+        internal_format, internal_format::TextElement, output_fmt::html::HtmlWriter,
+        output_fmt::Formatter,
+    };
 
     #[test]
     pub fn text_writer_fmt() {
@@ -184,7 +608,7 @@ mod test {
         ];
         for test_case in test_cases {
             let (test, expected_result) = test_case;
-            assert_eq!(HtmlWriter { text: test }.to_string(), expected_result)
+            assert_eq!(HtmlWriter::new().render(&test).unwrap(), expected_result)
         }
     }
 
@@ -233,4 +657,452 @@ mod test {
             assert_eq!(HtmlWriter::from_text(test).to_string(), expected_result)
         }
     }
+
+    #[test]
+    pub fn html_writer_hyperlink() {
+        let text = internal_format::Text {
+            text: vec![
+                TextElement::Marker(crate::common::Toggle::Set(
+                    internal_format::Fromatting::Hyperlink(
+                        "https://example.com/?a=1&b=\"2\"".to_string(),
+                    ),
+                )),
+                TextElement::Marker(crate::common::Toggle::Set(
+                    internal_format::Fromatting::Bold,
+                )),
+                TextElement::Text("link".to_string()),
+                TextElement::Marker(crate::common::Toggle::UnSet(
+                    internal_format::Fromatting::Hyperlink(String::new()),
+                )),
+                TextElement::Text(" text ".to_string()),
+                TextElement::Marker(crate::common::Toggle::Set(
+                    internal_format::Fromatting::Hyperlink("file:///tmp".to_string()),
+                )),
+                TextElement::Text("open".to_string()),
+            ],
+        };
+        assert_eq!(
+            HtmlWriter::new().render(&text).unwrap(),
+            "<a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\"><span style=\"font-weight:bold;\">link</span></a><span style=\"font-weight:bold;\"> text </span><a href=\"file:///tmp\"><span style=\"font-weight:bold;\">open</span></a>"
+        );
+    }
+
+    #[test]
+    pub fn html_writer_palette() {
+        let text = internal_format::Text {
+            text: vec![
+                TextElement::Marker(crate::common::Toggle::Set(
+                    internal_format::Fromatting::ForgroundColor(Some(
+                        crate::common::AnsiColor::Named(1),
+                    )),
+                )),
+                TextElement::Text("red".to_string()),
+            ],
+        };
+        let options = crate::output_fmt::Options {
+            palette: crate::palette::Palette::named("dracula").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            HtmlWriter::from_options(&options).render(&text).unwrap(),
+            "<span style=\"color:#ff5555;\">red</span>"
+        );
+    }
+
+    #[test]
+    pub fn html_writer_replaces_colors() {
+        let red = crate::common::AnsiColor::Named(1);
+        let green = crate::common::AnsiColor::Named(2);
+        let text = internal_format::Text {
+            text: vec![
+                TextElement::Marker(crate::common::Toggle::Set(
+                    internal_format::Fromatting::ForgroundColor(Some(red)),
+                )),
+                TextElement::Text("red".to_string()),
+                TextElement::Marker(crate::common::Toggle::Set(
+                    internal_format::Fromatting::ForgroundColor(Some(green)),
+                )),
+                TextElement::Text("green".to_string()),
+                TextElement::Marker(crate::common::Toggle::UnSet(
+                    internal_format::Fromatting::ForgroundColor(None),
+                )),
+                TextElement::Text("plain".to_string()),
+            ],
+        };
+        assert_eq!(
+            HtmlWriter::new().render(&text).unwrap(),
+            "<span style=\"color:#800000;\">red</span><span style=\"color:#008000;\">green</span>plain"
+        );
+    }
+
+    #[test]
+    pub fn html_writer_reverse_conceal_blink() {
+        let text = crate::internal_format::Text::from_ansi(crate::input_fmt::ansi::Text::from(
+            "\x1b[7mbar\x1b[31m red\x1b[0m \x1b[8msecret\x1b[28m \x1b[5mslow\x1b[6mfast\x1b[25m"
+                .to_string(),
+        ));
+        assert_eq!(
+            HtmlWriter::new().render(&text).unwrap(),
+            "<span style=\"color:#000000;background-color:#c0c0c0;\">bar</span>\
+            <span style=\"color:#000000;background-color:#800000;\"> red</span>        \
+            <style>@keyframes ansi-blink{50%{opacity:0}}</style>\
+            <span style=\"animation:ansi-blink 1s step-end infinite;\">slow</span>\
+            <span style=\"animation:ansi-blink 0.4s step-end infinite;\">fast</span>"
+        );
+        let options = crate::output_fmt::Options {
+            reveal: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            HtmlWriter::from_options(&options)
+                .render(&crate::internal_format::Text::from_ansi(
+                    crate::input_fmt::ansi::Text::from("\x1b[8msecret".to_string())
+                ))
+                .unwrap(),
+            "secret"
+        );
+    }
+
+    #[test]
+    pub fn html_writer_decorations() {
+        let render = |input: &str| {
+            HtmlWriter::new()
+                .render(&crate::internal_format::Text::from_ansi(
+                    crate::input_fmt::ansi::Text::from(input.to_string()),
+                ))
+                .unwrap()
+        };
+        assert_eq!(
+            render("\x1b[4mone\x1b[24m \x1b[9mtwo\x1b[29m"),
+            "<span style=\"text-decoration-line:underline;\">one</span> \
+            <span style=\"text-decoration-line:line-through;\">two</span>"
+        );
+        assert_eq!(
+            render("\x1b[21;53;9mall\x1b[55m\x1b[4:3;58;5;1mcurly\x1b[59mplain\x1b[0m"),
+            "<span style=\"text-decoration-line:underline overline line-through;text-decoration-style:double;\">all</span>\
+            <span style=\"text-decoration-line:underline line-through;text-decoration-style:wavy;text-decoration-color:#800000;\">curly</span>\
+            <span style=\"text-decoration-line:underline line-through;text-decoration-style:wavy;\">plain</span>"
+        );
+        assert_eq!(
+            render("\x1b[4:4mdotted\x1b[4:5mdashed\x1b[4:0mnone"),
+            "<span style=\"text-decoration-line:underline;text-decoration-style:dotted;\">dotted</span>\
+            <span style=\"text-decoration-line:underline;text-decoration-style:dashed;\">dashed</span>none"
+        );
+    }
+
+    #[test]
+    pub fn html_writer_fonts_frames_scripts() {
+        let text = crate::internal_format::Text::from_ansi(crate::input_fmt::ansi::Text::from(
+            "x\x1b[73m2\x1b[74mi\x1b[75m \x1b[51mbox\x1b[52mcircle\x1b[54m \x1b[20mold\x1b[12malt\x1b[10m"
+                .to_string(),
+        ));
+        assert_eq!(
+            HtmlWriter::new().render(&text).unwrap(),
+            "x<sup>2</sup><sub>i</sub> \
+            <span style=\"border:1px solid;\">box</span>\
+            <span style=\"border:1px solid;border-radius:1em;\">circle</span> \
+            <span style=\"font-family:UnifrakturMaguntia, fantasy;\">old</span>alt"
+        );
+        let options = crate::output_fmt::Options {
+            fonts: vec![
+                (2, "serif".to_string()),
+                (10, "\"Old English\"".to_string()),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            HtmlWriter::from_options(&options).render(&text).unwrap(),
+            "x<sup>2</sup><sub>i</sub> \
+            <span style=\"border:1px solid;\">box</span>\
+            <span style=\"border:1px solid;border-radius:1em;\">circle</span> \
+            <span style=\"font-family:&quot;Old English&quot;;\">old</span>\
+            <span style=\"font-family:serif;\">alt</span>"
+        );
+    }
+
+    #[test]
+    pub fn html_writer_escapes_hostile_input() {
+        let render = |input: &str| {
+            HtmlWriter::new()
+                .render(&crate::internal_format::Text::from_ansi(
+                    crate::input_fmt::ansi::Text::from(input.to_string()),
+                ))
+                .unwrap()
+        };
+        for (input, expected_result) in [
+            (
+                "<script>alert(1)</script>",
+                "&lt;script&gt;alert(1)&lt;/script&gt;",
+            ),
+            (
+                "error: no match for std::vector<int> && 'x' \"y\"",
+                "error: no match for std::vector&lt;int&gt; &amp;&amp; 'x' \"y\"",
+            ),
+            ("&amp; &lt;", "&amp;amp; &amp;lt;"),
+            (
+                "\x1b[1m<img src=x onerror=alert(1)>",
+                "<span style=\"font-weight:bold;\">&lt;img src=x onerror=alert(1)&gt;</span>",
+            ),
+            (
+                "\x1b]8;;https://example.com/\"><script>alert(1)</script>\x1b\\x\x1b]8;;\x1b\\",
+                "<a href=\"https://example.com/&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;\">x</a>",
+            ),
+            (
+                "\x1b]8;;HTTPS://example.com/\x1b\\x\x1b]8;;\x1b\\",
+                "<a href=\"HTTPS://example.com/\">x</a>",
+            ),
+            (
+                "\x1b]8;;mailto:a@example.com\x1b\\x\x1b]8;;\x1b\\",
+                "<a href=\"mailto:a@example.com\">x</a>",
+            ),
+            ("\x1b]8;;javascript:alert(1)\x1b\\x\x1b]8;;\x1b\\", "x"),
+            ("\x1b]8;;JavaScript:alert(1)\x1b\\x\x1b]8;;\x1b\\", "x"),
+            ("\x1b]8;; javascript:alert(1)\x1b\\x\x1b]8;;\x1b\\", "x"),
+            ("\x1b]8;;java\tscript:alert(1)\x1b\\x\x1b]8;;\x1b\\", "x"),
+            (
+                "\x1b]8;;data:text/html,<script>alert(1)</script>\x1b\\x\x1b]8;;\x1b\\",
+                "x",
+            ),
+            ("\x1b]8;;vbscript:msgbox(1)\x1b\\x\x1b]8;;\x1b\\", "x"),
+            ("\x1b]8;;//example.com\x1b\\x\x1b]8;;\x1b\\", "x"),
+            (
+                "\x1b]8;;javascript:alert(1)\x1b\\bad\x1b]8;;https://example.com\x1b\\good",
+                "bad<a href=\"https://example.com\">good</a>",
+            ),
+        ] {
+            assert_eq!(render(input), expected_result, "{:?}", input);
+        }
+        let options = crate::output_fmt::Options {
+            fonts: vec![(1, "\"></span><script>alert(1)</script>".to_string())],
+            ..Default::default()
+        };
+        assert_eq!(
+            HtmlWriter::from_options(&options)
+                .render(&crate::internal_format::Text::from_ansi(
+                    crate::input_fmt::ansi::Text::from("\x1b[11mx".to_string())
+                ))
+                .unwrap(),
+            "<span style=\"font-family:&quot;/spanscriptalert(1)/script;\">x</span>"
+        );
+    }
+
+    #[test]
+    pub fn html_writer_document() {
+        let text = crate::internal_format::Text::from_ansi(crate::input_fmt::ansi::Text::from(
+            "\x1b]8;;https://example.com\x1b\\\x1b[1m\u{2500} a  b\n".to_string(),
+        ));
+        let options = crate::output_fmt::Options {
+            html_mode: super::HtmlMode::Document,
+            title: Some("build <42>".to_string()),
+            palette: crate::palette::Palette::named("dracula").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            HtmlWriter::from_options(&options).render(&text).unwrap(),
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>build &lt;42&gt;</title>\n\
+            <style>body{color:#f8f8f2;background-color:#282a36;}\
+            pre{font-family:ui-monospace, 'Cascadia Mono', 'DejaVu Sans Mono', Menlo, Consolas, 'Liberation Mono', monospace;}</style>\n\
+            </head>\n<body>\n<pre>\n\
+            <a href=\"https://example.com\"><span style=\"font-weight:bold;\">\u{2500} a  b\n</span></a>\
+            </pre>\n</body>\n</html>\n"
+        );
+        let mut stream = crate::output_fmt::Stream::new(
+            crate::output_fmt::new(
+                crate::output_fmt::OutputFormat::Html,
+                &options,
+                &Default::default(),
+            ),
+            Vec::new(),
+        );
+        stream.write(&text).unwrap();
+        stream.write(&text).unwrap();
+        let output = String::from_utf8(stream.finish().unwrap()).unwrap();
+        assert_eq!(output.matches("<!DOCTYPE html>").count(), 1);
+        assert!(output.ends_with("</a></pre>\n</body>\n</html>\n"));
+        assert_eq!(
+            HtmlWriter::new().render(&text).unwrap(),
+            "<a href=\"https://example.com\"><span style=\"font-weight:bold;\">\u{2500} a  b\n</span></a>"
+        );
+    }
+
+    #[test]
+    pub fn html_writer_css_classes() {
+        let text = crate::internal_format::Text::from_ansi(crate::input_fmt::ansi::Text::from(
+            "\x1b[1;31mred\x1b[38;2;255;136;0m rgb\x1b[0m \x1b[4;9;5mx\x1b[0m plain".to_string(),
+        ));
+        let options = crate::output_fmt::Options {
+            css_classes: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            HtmlWriter::from_options(&options).render(&text).unwrap(),
+            "<span class=\"ansi-bold ansi-fg-1\">red</span>\
+            <span class=\"ansi-bold ansi-fg-rgb-ff8800\"> rgb</span> \
+            <span class=\"ansi-underline-line-through ansi-blink\">x</span> plain<style>\n\
+            @keyframes ansi-blink{50%{opacity:0}}\n\
+            .ansi-blink{animation:ansi-blink 1s step-end infinite;}\n\
+            .ansi-bold{font-weight:bold;}\n\
+            .ansi-fg-1{color:#800000;}\n\
+            .ansi-fg-rgb-ff8800{color:#ff8800;}\n\
+            .ansi-underline-line-through{text-decoration-line:underline line-through;}\n\
+            </style>\n"
+        );
+
+        // writers given the same stylesheet share it e.g. when each file is converted on its own thread
+        let options = crate::output_fmt::Options {
+            css_classes: true,
+            class_prefix: "log-".to_string(),
+            ..Default::default()
+        };
+        let stylesheet = std::sync::Arc::new(super::Stylesheet::default());
+        let mut buffer: Vec<u8> = Vec::new();
+        HtmlWriter::from_options(&options)
+            .with_stylesheet(stylesheet.clone())
+            .body(&mut buffer, &text)
+            .unwrap();
+        let mut stream = crate::output_fmt::Stream::new(
+            crate::output_fmt::new(crate::output_fmt::OutputFormat::Html, &options, &stylesheet),
+            Vec::new(),
+        );
+        stream.write_formatted(&buffer).unwrap();
+        let output = String::from_utf8(stream.finish().unwrap()).unwrap();
+        assert!(output.starts_with("<span class=\"log-bold log-fg-1\">red</span>"));
+        assert!(output.contains("\n.log-fg-rgb-ff8800{color:#ff8800;}\n"));
+        assert!(!output.contains("style=\""));
+    }
+
+    #[test]
+    pub fn html_writer_stylesheet_file() {
+        let path = std::env::temp_dir().join(format!("ansi_fmt_{}.css", std::process::id()));
+        let options = crate::output_fmt::Options {
+            css_classes: true,
+            html_mode: super::HtmlMode::Document,
+            stylesheet_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let output = HtmlWriter::from_options(&options)
+            .render(&crate::internal_format::Text::from_ansi(
+                crate::input_fmt::ansi::Text::from("\x1b[3mx".to_string()),
+            ))
+            .unwrap();
+        let stylesheet = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(output.contains(&format!(
+            "<link rel=\"stylesheet\" href=\"{}\">\n</head>",
+            super::path_to_href(&path)
+        )));
+        assert!(!output.contains("<style"));
+        assert!(output
+            .ends_with("<pre>\n<span class=\"ansi-italic\">x</span></pre>\n</body>\n</html>\n"));
+        assert!(stylesheet.starts_with("body{color:#"));
+        assert!(stylesheet.contains("pre{font-family:"));
+        assert!(stylesheet.ends_with("}\n.ansi-italic{font-style:italic;}\n"));
+
+        let options = crate::output_fmt::Options {
+            stylesheet_href: Some("/css/log \"1\".css".to_string()),
+            ..options
+        };
+        let output = HtmlWriter::from_options(&options)
+            .render(&crate::internal_format::Text::from_ansi(
+                crate::input_fmt::ansi::Text::from("x".to_string()),
+            ))
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(output.contains("<link rel=\"stylesheet\" href=\"/css/log &quot;1&quot;.css\">\n"));
+    }
+
+    #[test]
+    pub fn html_writer_path_to_href() {
+        let tests = [
+            ("log.css", "log.css"),
+            ("./css/log.css", "css/log.css"),
+            ("../css/my log.css", "../css/my%20log.css"),
+            ("/tmp/log#1.css", "file:///tmp/log%231.css"),
+        ];
+        for (path, expected_result) in tests {
+            assert_eq!(
+                super::path_to_href(std::path::Path::new(path)),
+                expected_result,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    pub fn html_writer_document_css_classes() {
+        let options = crate::output_fmt::Options {
+            css_classes: true,
+            html_mode: super::HtmlMode::Document,
+            class_prefix: "log-".to_string(),
+            ..Default::default()
+        };
+        let stylesheet = std::sync::Arc::new(super::Stylesheet::default());
+        let mut stream = crate::output_fmt::Stream::new(
+            crate::output_fmt::new(crate::output_fmt::OutputFormat::Html, &options, &stylesheet),
+            Vec::new(),
+        );
+        stream
+            .write(&crate::internal_format::Text::from_ansi(
+                crate::input_fmt::ansi::Text::from("\x1b[5mx".to_string()),
+            ))
+            .unwrap();
+        // text formatted on another thread goes into the document's body as well
+        let mut buffer: Vec<u8> = Vec::new();
+        HtmlWriter::from_options(&options)
+            .with_stylesheet(stylesheet.clone())
+            .body(
+                &mut buffer,
+                &crate::internal_format::Text::from_ansi(crate::input_fmt::ansi::Text::from(
+                    "\x1b[3my".to_string(),
+                )),
+            )
+            .unwrap();
+        stream.write_formatted(&buffer).unwrap();
+        let output = String::from_utf8(stream.finish().unwrap()).unwrap();
+        let (head, body) = output.split_once("</head>").unwrap();
+        assert!(head.contains("<style>body{color:#"));
+        // the stylesheet is only known at the end so it comes after the text rather than in the head
+        assert_eq!(
+            body,
+            "\n<body>\n<pre>\n<span class=\"log-blink\">x</span>\
+            <span class=\"log-italic\">y</span></pre>\n<style>\n\
+            @keyframes log-blink{50%{opacity:0}}\n\
+            .log-blink{animation:log-blink 1s step-end infinite;}\n\
+            .log-italic{font-style:italic;}\n</style>\n</body>\n</html>\n"
+        );
+
+        // without classes the keyframes are still named with the prefix
+        let options = crate::output_fmt::Options {
+            class_prefix: "log-".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            HtmlWriter::from_options(&options)
+                .render(&crate::internal_format::Text::from_ansi(
+                    crate::input_fmt::ansi::Text::from("\x1b[5mx".to_string()),
+                ))
+                .unwrap(),
+            "<style>@keyframes log-blink{50%{opacity:0}}</style>\
+            <span style=\"animation:log-blink 1s step-end infinite;\">x</span>"
+        );
+    }
+
+    #[test]
+    pub fn html_writer_runs() {
+        let text = crate::internal_format::Text::from_ansi(crate::input_fmt::ansi::Text::from(
+            "\x1b[1ma\x1b[3m\x1b[23mb\x1b[0m".to_string(),
+        ));
+        let expected_result = "<span style=\"font-weight:bold;\">ab</span>";
+        assert_eq!(HtmlWriter::new().render(&text).unwrap(), expected_result);
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut writer = HtmlWriter::new();
+        for element in text.text.iter() {
+            writer.element(&mut buffer, element).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "<span style=\"font-weight:bold;\">a</span><span style=\"font-weight:bold;\">b</span>"
+        );
+    }
 }
@@ -1,9 +1,13 @@
 pub mod html;
 pub mod text;
 
-use clap::ValueEnum;
+use clap::{Args, ValueEnum};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use crate::internal_format;
+use crate::palette::Palette;
 
 use self::html::HtmlWriter;
 use self::text::TextWriter;
@@ -14,9 +18,235 @@ pub enum OutputFormat {
     Html,
 }
 
-pub trait Formatter: ToString {}
+/// The options that can be used to change how the writers format their output
+#[derive(Args, Clone, Debug)]
+pub struct Options {
+    /// When writing text append the URL of each hyperlink after the text of the link.
+    #[arg(long)]
+    pub show_urls: bool,
+    /// Show text that was concealed (SGR 8) e.g. a password rather than
+    /// hiding it.
+    #[arg(long)]
+    pub reveal: bool,
+    /// The palette used for the 16 base colours, this is either the name of a
+    /// built in palette (default, xterm, vga, windows10, solarized, tango or
+    /// dracula) or the path to a palette file with a `<colour> = #rrggbb` line
+    /// for each colour that is changed e.g. `bright-red = #ff5555`.
+    #[arg(long, default_value = "default", value_parser = Palette::from_arg)]
+    pub palette: Palette,
+    /// The font family used in HTML for text in one of the alternate fonts,
+    /// given as `<font>=<family>` where the font is 1-9 for the alternate
+    /// fonts (SGR 11-19) or 10 for Fraktur (SGR 20) e.g.
+    /// `--font 10=UnifrakturMaguntia`. This can be given more than once.
+    #[arg(long = "font", value_name = "FONT=FAMILY", value_parser = parse_font)]
+    pub fonts: Vec<(u8, String)>,
+    /// Write HTML as a fragment that can be embedded in another page or as a
+    /// complete document that can be opened on its own.
+    #[arg(long, value_enum, default_value_t = html::HtmlMode::Fragment)]
+    pub html_mode: html::HtmlMode,
+    /// The title of the page when writing an HTML document.
+    #[arg(long)]
+    pub title: Option<String>,
+    /// Style HTML with classes and a stylesheet rather than inline styles,
+    /// this makes the output smaller and works under a Content-Security-Policy
+    /// that does not allow inline styles.
+    #[arg(long)]
+    pub css_classes: bool,
+    /// The prefix of the class names used by `--css-classes`.
+    #[arg(long, default_value = html::DEFAULT_CLASS_PREFIX, value_parser = parse_class_prefix)]
+    pub class_prefix: String,
+    /// Write the stylesheet used by `--css-classes` to this file rather than
+    /// embedding it in a `<style>` block, an HTML document links to it.
+    #[arg(long, value_name = "FILE")]
+    pub stylesheet_file: Option<PathBuf>,
+    /// The URL an HTML document uses to link to the stylesheet file, by
+    /// default this is the path given to `--stylesheet-file`.
+    #[arg(long, value_name = "URL")]
+    pub stylesheet_href: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            show_urls: false,
+            reveal: false,
+            palette: Palette::default(),
+            fonts: Vec::new(),
+            html_mode: html::HtmlMode::Fragment,
+            title: None,
+            css_classes: false,
+            class_prefix: html::DEFAULT_CLASS_PREFIX.to_string(),
+            stylesheet_file: None,
+            stylesheet_href: None,
+        }
+    }
+}
+
+/// Parses the value given to `--class-prefix`, this has to be usable at the start of a CSS class name
+fn parse_class_prefix(arg: &str) -> Result<String, String> {
+    match arg.chars().next() {
+        None => Err("the prefix can not be empty".to_string()),
+        Some(c) if c.is_ascii_digit() => Err("the prefix can not start with a digit".to_string()),
+        Some('-') => Err("the prefix can not start with -".to_string()),
+        _ if arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+        {
+            Ok(arg.to_string())
+        }
+        _ => Err("the prefix can only contain letters, digits, - and _".to_string()),
+    }
+}
+
+/// Parses the value given to `--font`
+fn parse_font(arg: &str) -> Result<(u8, String), String> {
+    let (font, family) = arg
+        .split_once('=')
+        .ok_or_else(|| "expected <font>=<family>".to_string())?;
+    match font.trim().parse::<u8>() {
+        Ok(font @ 1..=10) if !family.trim().is_empty() => Ok((font, family.trim().to_string())),
+        Ok(1..=10) => Err("the font family is empty".to_string()),
+        _ => Err(format!("{} is not a font between 1 and 10", font.trim())),
+    }
+}
+
+/// A push based writer that converts our internal text into a particular output format.
+///
+/// The text is handed over one element at a time and written straight to the output so the whole of the text
+/// never needs to be held in memory. `begin` and `end` are used to write anything that has to appear once at
+/// the start or end of the output, use a [`Stream`] to make sure that they are only called once.
+pub trait Formatter {
+    /// Writes out the prologue of the document, this is called once before any element is written
+    fn begin(&mut self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes out a single element of the text
+    fn element(
+        &mut self,
+        out: &mut dyn Write,
+        element: &internal_format::TextElement,
+    ) -> io::Result<()>;
+
+    /// Closes anything that was left open by the elements written so far e.g. a hyperlink that was never ended
+    fn close(&mut self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes out the epilogue of the document, this is called once after the last element is written
+    fn end(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.close(out)
+    }
+
+    /// Writes out every element of the text given with out a prologue or epilogue
+    fn body(&mut self, out: &mut dyn Write, text: &internal_format::Text) -> io::Result<()> {
+        for element in text.text.iter() {
+            self.element(out, element)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the text given as a complete document into a String
+    fn render(&mut self, text: &internal_format::Text) -> io::Result<String> {
+        let mut buffer: Vec<u8> = Vec::new();
+        self.begin(&mut buffer)?;
+        self.body(&mut buffer, text)?;
+        self.end(&mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Ties a Formatter to its output making sure that the prologue and epilogue are each written exactly once no
+/// matter how many pieces of text are written in between.
+pub struct Stream<W: Write> {
+    formatter: Box<dyn Formatter>,
+    out: W,
+    started: bool,
+}
+
+impl<W: Write> Stream<W> {
+    pub fn new(formatter: Box<dyn Formatter>, out: W) -> Stream<W> {
+        Stream {
+            formatter,
+            out,
+            started: false,
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            self.formatter.begin(&mut self.out)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the next piece of text
+    pub fn write(&mut self, text: &internal_format::Text) -> io::Result<()> {
+        self.start()?;
+        self.formatter.body(&mut self.out, text)
+    }
+
+    /// Writes out output that has already been formatted e.g. a body rendered on another thread
+    pub fn write_formatted(&mut self, formatted: &[u8]) -> io::Result<()> {
+        self.start()?;
+        self.out.write_all(formatted)
+    }
+
+    /// Writes the epilogue and flushes the output, returning the output so that it can be reused
+    pub fn finish(mut self) -> io::Result<W> {
+        self.start()?;
+        self.formatter.end(&mut self.out)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
 
-pub fn from(fmt: OutputFormat, text: internal_format::Text) -> Option<Box<dyn Formatter>> {
+/// A piece of text paired with the formatter used to write it, this allows the output to be produced with
+/// to_string when streaming is not needed.
+pub struct Rendered<F: Formatter + Clone> {
+    formatter: F,
+    text: internal_format::Text,
+}
+
+impl<F: Formatter + Clone> Rendered<F> {
+    pub fn new(formatter: F, text: internal_format::Text) -> Rendered<F> {
+        Rendered { formatter, text }
+    }
+}
+
+impl<F: Formatter + Clone> std::fmt::Display for Rendered<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.formatter.clone().render(&self.text) {
+            Ok(rendered) => f.write_str(&rendered),
+            Err(_) => Err(std::fmt::Error),
+        }
+    }
+}
+
+/// Replaces every character of the text with a space keeping the line breaks so the layout stays the same
+pub fn conceal(text: &str) -> String {
+    text.chars()
+        .map(|c| if c == '\n' { c } else { ' ' })
+        .collect()
+}
+
+/// Returns a new Formatter for the output format given, the HTML writer adds the classes it uses to the stylesheet
+/// given so that the formatters for each part of the same output share one stylesheet.
+pub fn new(
+    fmt: OutputFormat,
+    options: &Options,
+    stylesheet: &Arc<html::Stylesheet>,
+) -> Box<dyn Formatter> {
+    match fmt {
+        OutputFormat::Text => Box::new(TextWriter::from_options(options)),
+        OutputFormat::Html => {
+            Box::new(HtmlWriter::from_options(options).with_stylesheet(Arc::clone(stylesheet)))
+        }
+    }
+}
+
+pub fn from(fmt: OutputFormat, text: internal_format::Text) -> Option<Box<dyn ToString>> {
     match fmt {
         OutputFormat::Text => Some(Box::new(TextWriter::from_text(text))),
         OutputFormat::Html => Some(Box::new(HtmlWriter::from_text(text))),
@@ -78,4 +308,93 @@ mod test {
             )
         }
     }
+
+    struct Bracketed {}
+
+    impl super::Formatter for Bracketed {
+        fn begin(&mut self, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+            out.write_all(b"<")
+        }
+
+        fn element(
+            &mut self,
+            out: &mut dyn std::io::Write,
+            element: &TextElement,
+        ) -> std::io::Result<()> {
+            if let TextElement::Text(t) = element {
+                out.write_all(t.as_bytes())?;
+            }
+            Ok(())
+        }
+
+        fn end(&mut self, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+            out.write_all(b">")
+        }
+    }
+
+    #[test]
+    pub fn stream() {
+        let test_cases = [
+            (vec![], "<>"),
+            (vec!["one"], "<one>"),
+            (vec!["one", " two", " three"], "<one two three>"),
+        ];
+        for test_case in test_cases {
+            let (texts, expected_result) = test_case;
+            let mut stream = super::Stream::new(Box::new(Bracketed {}), Vec::new());
+            for text in texts {
+                stream
+                    .write(&internal_format::Text {
+                        text: vec![TextElement::Text(text.to_string())],
+                    })
+                    .unwrap();
+            }
+            assert_eq!(
+                String::from_utf8(stream.finish().unwrap()).unwrap(),
+                expected_result
+            );
+        }
+    }
+
+    #[test]
+    pub fn parse_font() {
+        assert_eq!(
+            super::parse_font("10= UnifrakturMaguntia"),
+            Ok((10, "UnifrakturMaguntia".to_string()))
+        );
+        assert_eq!(super::parse_font("1=a=b"), Ok((1, "a=b".to_string())));
+        for (arg, expected_result) in [
+            ("serif", "expected <font>=<family>"),
+            ("0=serif", "0 is not a font between 1 and 10"),
+            ("11=serif", "11 is not a font between 1 and 10"),
+            ("x=serif", "x is not a font between 1 and 10"),
+            ("3= ", "the font family is empty"),
+        ] {
+            assert_eq!(super::parse_font(arg), Err(expected_result.to_string()));
+        }
+    }
+
+    #[test]
+    pub fn parse_class_prefix() {
+        assert_eq!(
+            super::parse_class_prefix("log_1-"),
+            Ok("log_1-".to_string())
+        );
+        assert_eq!(
+            super::parse_class_prefix(""),
+            Err("the prefix can not be empty".to_string())
+        );
+        assert_eq!(
+            super::parse_class_prefix("-x"),
+            Err("the prefix can not start with -".to_string())
+        );
+        assert_eq!(
+            super::parse_class_prefix("1x"),
+            Err("the prefix can not start with a digit".to_string())
+        );
+        assert_eq!(
+            super::parse_class_prefix("a b"),
+            Err("the prefix can only contain letters, digits, - and _".to_string())
+        );
+    }
 }
@@ -1,35 +1,100 @@
-use crate::internal_format;
-use crate::output_fmt;
+use crate::internal_format::{self, Style};
+use crate::output_fmt::{self, Formatter};
+use std::io::{self, Write};
 
+/// Writes out just the text with all of the formatting removed
+#[derive(Clone, Debug, Default)]
 pub struct TextWriter {
-    text: internal_format::Text,
+    show_urls: bool,
+    reveal: bool,
+    style: Style,
+    hyperlink: Option<String>,
 }
 
 impl TextWriter {
-    pub fn from_text(txt: internal_format::Text) -> TextWriter {
-        TextWriter { text: txt }
+    pub fn new() -> TextWriter {
+        TextWriter {
+            show_urls: false,
+            reveal: false,
+            style: Style::default(),
+            hyperlink: None,
+        }
+    }
+
+    pub fn from_options(options: &output_fmt::Options) -> TextWriter {
+        TextWriter {
+            show_urls: options.show_urls,
+            reveal: options.reveal,
+            ..TextWriter::new()
+        }
+    }
+
+    pub fn from_text(txt: internal_format::Text) -> output_fmt::Rendered<TextWriter> {
+        output_fmt::Rendered::new(TextWriter::new(), txt)
+    }
+
+    /// Writes out a run of text in the style given
+    fn run(&mut self, out: &mut dyn Write, style: &Style, text: &str) -> io::Result<()> {
+        if self.hyperlink != style.hyperlink {
+            self.close(out)?;
+            self.hyperlink = style.hyperlink.clone();
+        }
+        match style.conceal && !self.reveal {
+            true => out.write_all(output_fmt::conceal(text).as_bytes()),
+            false => out.write_all(text.as_bytes()),
+        }
+    }
+
+    /// Writes out the URL of the hyperlink as soon as the link ends
+    fn end_hyperlink(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if self.hyperlink.is_some() && self.hyperlink != self.style.hyperlink {
+            self.close(out)?;
+        }
+        Ok(())
     }
 }
 
-impl std::fmt::Display for TextWriter {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for element in self.text.text.iter() {
-            if let internal_format::TextElement::Text(t) = element {
-                match f.write_str(t.as_str()) {
-                    Err(e) => return Err(e),
-                    _ => {}
-                }
+impl output_fmt::Formatter for TextWriter {
+    fn element(
+        &mut self,
+        out: &mut dyn Write,
+        element: &internal_format::TextElement,
+    ) -> io::Result<()> {
+        match element {
+            internal_format::TextElement::Text(t) => {
+                let style = self.style.clone();
+                self.run(out, &style, t)
+            }
+            internal_format::TextElement::Marker(m) => {
+                self.style.apply(m);
+                self.end_hyperlink(out)
+            }
+        }
+    }
+
+    fn body(&mut self, out: &mut dyn Write, text: &internal_format::Text) -> io::Result<()> {
+        for (style, t) in text.runs_from(&mut self.style) {
+            self.run(out, &style, &t)?;
+        }
+        self.end_hyperlink(out)
+    }
+
+    fn close(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if let Some(uri) = self.hyperlink.take() {
+            if self.show_urls {
+                write!(out, " <{}>", uri)?;
             }
         }
         Ok(())
     }
 }
 
-impl output_fmt::Formatter for TextWriter {}
-
 #[cfg(test)]
 mod test {
-    use crate::{internal_format, internal_format::TextElement, output_fmt::text::TextWriter};
+    use crate::{
+        internal_format, internal_format::TextElement, output_fmt::text::TextWriter,
+        output_fmt::Formatter,
+    };
 
     #[test]
     pub fn text_writer_fmt() {
@@ -73,7 +138,7 @@ mod test {
         ];
         for test_case in test_cases {
             let (test, expected_result) = test_case;
-            assert_eq!(TextWriter { text: test }.to_string(), expected_result)
+            assert_eq!(TextWriter::new().render(&test).unwrap(), expected_result)
         }
     }
 
@@ -122,4 +187,62 @@ mod test {
             assert_eq!(TextWriter::from_text(test).to_string(), expected_result)
         }
     }
+
+    #[test]
+    pub fn text_writer_show_urls() {
+        let text = internal_format::Text {
+            text: vec![
+                TextElement::Marker(crate::common::Toggle::Set(
+                    internal_format::Fromatting::Hyperlink("https://example.com".to_string()),
+                )),
+                TextElement::Text("link".to_string()),
+                TextElement::Marker(crate::common::Toggle::UnSet(
+                    internal_format::Fromatting::Hyperlink(String::new()),
+                )),
+                TextElement::Text(" and ".to_string()),
+                TextElement::Marker(crate::common::Toggle::Set(
+                    internal_format::Fromatting::Hyperlink("file:///tmp".to_string()),
+                )),
+                TextElement::Text("tmp".to_string()),
+            ],
+        };
+        assert_eq!(TextWriter::new().render(&text).unwrap(), "link and tmp");
+        let options = crate::output_fmt::Options {
+            show_urls: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            TextWriter::from_options(&options).render(&text).unwrap(),
+            "link <https://example.com> and tmp <file:///tmp>"
+        );
+    }
+
+    #[test]
+    pub fn text_writer_conceal() {
+        let text = internal_format::Text {
+            text: vec![
+                TextElement::Text("Password: ".to_string()),
+                TextElement::Marker(crate::common::Toggle::Set(
+                    internal_format::Fromatting::Conceal,
+                )),
+                TextElement::Text("hunter2\n".to_string()),
+                TextElement::Marker(crate::common::Toggle::UnSet(
+                    internal_format::Fromatting::Conceal,
+                )),
+                TextElement::Text("ok".to_string()),
+            ],
+        };
+        assert_eq!(
+            TextWriter::new().render(&text).unwrap(),
+            "Password:        \nok"
+        );
+        let options = crate::output_fmt::Options {
+            reveal: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            TextWriter::from_options(&options).render(&text).unwrap(),
+            "Password: hunter2\nok"
+        );
+    }
 }
@@ -0,0 +1,329 @@
+use crate::common::{self, AnsiColor, Color};
+
+/// The names of the palettes that are built in, these can be given to `--palette` instead of a file
+pub const NAMES: [&str; 7] = [
+    "default",
+    "xterm",
+    "vga",
+    "windows10",
+    "solarized",
+    "tango",
+    "dracula",
+];
+
+/// The names used for the 16 base colours in a palette file, the bright varients are prefixed with `bright-`
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// The colours used for the 16 base colours i.e. SGR 30-37, 40-47, 90-97, 100-107 and `38;5;0` to `38;5;15`, this
+/// is used to resolve an [`AnsiColor`] when the text is written out.
+///
+/// Indices 0-7 are the standard colours and 8-15 are their bright varients. The rest of the 256 colour palette is
+/// the same for every palette, see [`common::XTERM_PALETTE`].
+///
+/// The default palette uses [`common::black`] to [`common::white`] and their bright varients.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Palette {
+    pub colors: [Color; 16],
+    /// The colour of text that has no forground colour set
+    pub foreground: Color,
+    /// The colour behind text that has no background colour set
+    pub background: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let base = [
+            common::black(),
+            common::red(),
+            common::green(),
+            common::yellow(),
+            common::blue(),
+            common::magenta(),
+            common::cyan(),
+            common::white(),
+        ];
+        let mut colors = [common::black(); 16];
+        for (index, color) in base.into_iter().enumerate() {
+            colors[index] = color;
+            colors[index + 8] = Color::make_bright(color);
+        }
+        Palette {
+            colors,
+            foreground: common::white(),
+            background: common::black(),
+        }
+    }
+}
+
+impl Palette {
+    /// Returns the colour for an index of the 256 colour palette
+    pub fn color(&self, index: u8) -> Color {
+        match self.colors.get(usize::from(index)) {
+            Some(color) => *color,
+            None => common::XTERM_PALETTE[usize::from(index)],
+        }
+    }
+
+    /// Returns the 24 bit colour that the colour given is shown as when using this palette
+    pub fn resolve(&self, color: AnsiColor) -> Color {
+        match color {
+            AnsiColor::Named(index) | AnsiColor::Indexed(index) => self.color(index),
+            AnsiColor::Rgb(color) => color,
+        }
+    }
+
+    /// Returns the built in palette with the name given, see [`NAMES`]
+    pub fn named(name: &str) -> Option<Palette> {
+        let (colors, foreground, background) = match name {
+            "default" => return Some(Palette::default()),
+            // the system colours are the first 16 colours of the xterm palette
+            "xterm" => {
+                return Some(Palette {
+                    colors: std::array::from_fn(|index| common::XTERM_PALETTE[index]),
+                    foreground: common::black(),
+                    background: Color {
+                        red: 0xff,
+                        green: 0xff,
+                        blue: 0xff,
+                    },
+                })
+            }
+            "vga" => (
+                [
+                    0x000000, 0xaa0000, 0x00aa00, 0xaa5500, 0x0000aa, 0xaa00aa, 0x00aaaa, 0xaaaaaa,
+                    0x555555, 0xff5555, 0x55ff55, 0xffff55, 0x5555ff, 0xff55ff, 0x55ffff, 0xffffff,
+                ],
+                0xaaaaaa,
+                0x000000,
+            ),
+            "windows10" => (
+                [
+                    0x0c0c0c, 0xc50f1f, 0x13a10e, 0xc19c00, 0x0037da, 0x881798, 0x3a96dd, 0xcccccc,
+                    0x767676, 0xe74856, 0x16c60c, 0xf9f1a5, 0x3b78ff, 0xb4009e, 0x61d6d6, 0xf2f2f2,
+                ],
+                0xcccccc,
+                0x0c0c0c,
+            ),
+            "solarized" => (
+                [
+                    0x073642, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0xeee8d5,
+                    0x002b36, 0xcb4b16, 0x586e75, 0x657b83, 0x839496, 0x6c71c4, 0x93a1a1, 0xfdf6e3,
+                ],
+                0x839496,
+                0x002b36,
+            ),
+            "tango" => (
+                [
+                    0x000000, 0xcc0000, 0x4e9a06, 0xc4a000, 0x3465a4, 0x75507b, 0x06989a, 0xd3d7cf,
+                    0x555753, 0xef2929, 0x8ae234, 0xfce94f, 0x729fcf, 0xad7fa8, 0x34e2e2, 0xeeeeec,
+                ],
+                0xd3d7cf,
+                0x2e3436,
+            ),
+            "dracula" => (
+                [
+                    0x21222c, 0xff5555, 0x50fa7b, 0xf1fa8c, 0xbd93f9, 0xff79c6, 0x8be9fd, 0xf8f8f2,
+                    0x6272a4, 0xff6e6e, 0x69ff94, 0xffffa5, 0xd6acff, 0xff92df, 0xa4ffff, 0xffffff,
+                ],
+                0xf8f8f2,
+                0x282a36,
+            ),
+            _ => return None,
+        };
+        let rgb = |hex: u32| Color {
+            red: (hex >> 16) as u8,
+            green: (hex >> 8) as u8,
+            blue: hex as u8,
+        };
+        Some(Palette {
+            colors: colors.map(rgb),
+            foreground: rgb(foreground),
+            background: rgb(background),
+        })
+    }
+
+    /// Reads the palette given on the command line, this is either the name of a built in palette or the path to
+    /// a palette file.
+    pub fn from_arg(arg: &str) -> Result<Palette, String> {
+        match Palette::named(arg) {
+            Some(palette) => Ok(palette),
+            None => match std::fs::read_to_string(arg) {
+                Ok(contents) => Palette::parse(&contents).map_err(|e| format!("{}: {}", arg, e)),
+                Err(e) => Err(format!(
+                    "{} is not one of {} and could not be read: {}",
+                    arg,
+                    NAMES.join(", "),
+                    e
+                )),
+            },
+        }
+    }
+
+    /// Parses the contents of a palette file.
+    ///
+    /// Each line sets one colour in the form `<colour> = <hex>` where the colour is either its index (`0`-`15`),
+    /// its name e.g. `red` or `bright-red` or one of the default colours `foreground` and `background` and the hex
+    /// is written as `#rrggbb`. Blank lines and lines starting
+    /// with `#` are ignored, any colour that is not set is taken from the default palette.
+    pub fn parse(contents: &str) -> Result<Palette, String> {
+        let mut palette = Palette::default();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected <colour> = #rrggbb"))?;
+            let color = Palette::hex(value.trim())
+                .ok_or_else(|| error("expected a colour in the form #rrggbb"))?;
+            match key.trim() {
+                "foreground" => palette.foreground = color,
+                "background" => palette.background = color,
+                key => {
+                    let index = Palette::index(key).ok_or_else(|| error("unknown colour"))?;
+                    palette.colors[index] = color;
+                }
+            }
+        }
+        Ok(palette)
+    }
+
+    fn index(key: &str) -> Option<usize> {
+        if let Ok(index) = key.parse::<usize>() {
+            return (index < 16).then_some(index);
+        }
+        let (name, offset) = match key.strip_prefix("bright-") {
+            Some(name) => (name, 8),
+            None => (key, 0),
+        };
+        COLOR_NAMES
+            .iter()
+            .position(|color| *color == name)
+            .map(|index| index + offset)
+    }
+
+    fn hex(value: &str) -> Option<Color> {
+        let hex = value.strip_prefix('#')?;
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |start: usize| u8::from_str_radix(&hex[start..start + 2], 16).ok();
+        Some(Color {
+            red: channel(0)?,
+            green: channel(2)?,
+            blue: channel(4)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Palette, NAMES};
+    use crate::common::{self, AnsiColor, Color};
+
+    #[test]
+    fn palette_named() {
+        for name in NAMES {
+            assert!(Palette::named(name).is_some(), "{}", name);
+        }
+        assert_eq!(Palette::named("unknown"), None);
+        assert_eq!(Palette::named("default"), Some(Palette::default()));
+        assert_eq!(Palette::default().color(1), common::red());
+        assert_eq!(
+            Palette::default().color(9),
+            Color::make_bright(common::red())
+        );
+        let xterm = Palette::named("xterm").unwrap();
+        for index in 0..=255 {
+            assert_eq!(
+                xterm.color(index),
+                common::XTERM_PALETTE[usize::from(index)],
+                "{}",
+                index
+            );
+        }
+        let vga = Palette::named("vga").unwrap();
+        assert_eq!(
+            vga.color(3),
+            Color {
+                red: 0xaa,
+                green: 0x55,
+                blue: 0
+            }
+        );
+        assert_eq!(vga.color(196), common::XTERM_PALETTE[196]);
+        assert_eq!(vga.resolve(AnsiColor::Named(3)), vga.colors[3]);
+        assert_eq!(vga.resolve(AnsiColor::Indexed(9)), vga.colors[9]);
+        assert_eq!(
+            vga.resolve(AnsiColor::Indexed(21)),
+            common::XTERM_PALETTE[21]
+        );
+        assert_eq!(vga.resolve(AnsiColor::Rgb(common::red())), common::red());
+    }
+
+    #[test]
+    fn palette_parse() {
+        let palette =
+            Palette::parse("# my theme\n\n1 = #ff0000\nbright-blue=#0A0B0C\n  white = #ffffff  \nbackground = #ffffff\n")
+                .unwrap();
+        assert_eq!(
+            palette.color(1),
+            Color {
+                red: 255,
+                green: 0,
+                blue: 0
+            }
+        );
+        assert_eq!(
+            palette.color(12),
+            Color {
+                red: 10,
+                green: 11,
+                blue: 12
+            }
+        );
+        assert_eq!(
+            palette.color(7),
+            Color {
+                red: 255,
+                green: 255,
+                blue: 255
+            }
+        );
+        assert_eq!(palette.color(2), Palette::default().color(2));
+        assert_eq!(palette.background, palette.color(7));
+        assert_eq!(palette.foreground, Palette::default().foreground);
+        for (contents, expected_result) in [
+            ("red #ff0000", "line 1: expected <colour> = #rrggbb"),
+            ("\n16 = #ff0000", "line 2: unknown colour"),
+            ("orange = #ff8800", "line 1: unknown colour"),
+            (
+                "orange = #ff88",
+                "line 1: expected a colour in the form #rrggbb",
+            ),
+            (
+                "red = ff0000",
+                "line 1: expected a colour in the form #rrggbb",
+            ),
+            (
+                "red = #ff00",
+                "line 1: expected a colour in the form #rrggbb",
+            ),
+            (
+                "red = #gg0000",
+                "line 1: expected a colour in the form #rrggbb",
+            ),
+        ] {
+            assert_eq!(
+                Palette::parse(contents),
+                Err(expected_result.to_string()),
+                "{}",
+                contents
+            );
+        }
+    }
+}
@@ -0,0 +1,1086 @@
+use crate::common;
+use crate::input_fmt::ansi;
+use crate::input_fmt::overstrike::Overstrike;
+use crate::internal_format::{self, Fromatting};
+use crate::palette::Palette;
+use clap::{ArgAction, Args, ValueEnum};
+use std::collections::VecDeque;
+
+/// What is output by the terminal emulation once all of the input has been read
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum Emulation {
+    /// Only what was on the screen at the end
+    Screen,
+    /// Every line that scrolled off the top of the screen followed by what was on the screen at the end
+    Scrollback,
+}
+
+/// The options used to set up the terminal emulation
+#[derive(Args, Clone, Debug)]
+#[group(id = "TerminalOptions")]
+pub struct Options {
+    /// Run the input through a virtual terminal and output what would have
+    /// been seen on it rather than everything that was written to it.
+    #[arg(long, value_enum)]
+    pub terminal: Option<Emulation>,
+    /// The number of rows of the virtual terminal.
+    #[arg(long, default_value_t = 24)]
+    pub rows: usize,
+    /// The number of columns of the virtual terminal.
+    #[arg(long, default_value_t = 80)]
+    pub columns: usize,
+    /// The most lines that are kept once they have scrolled off the top of
+    /// the screen with `--terminal scrollback`, the oldest lines are dropped
+    /// once there are more than this.
+    #[arg(long, default_value_t = 10000)]
+    pub scrollback_lines: usize,
+    /// Apply carriage returns, backspaces and erase in line to each line so
+    /// only what was left on the line is output, this is much lighter than
+    /// emulating a terminal and is enough for most progress bars.
+    #[arg(long)]
+    pub line_discipline: bool,
+    /// Leave characters that have been struck over using a backspace as they
+    /// are, by default they are read the way nroff does e.g. in man pages,
+    /// `X\bX` is bold and `_\bX` is underlined.
+    #[arg(long = "no-overstrike", action = ArgAction::SetFalse)]
+    pub overstrike: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            terminal: None,
+            rows: 24,
+            columns: 80,
+            scrollback_lines: 10000,
+            line_discipline: false,
+            overstrike: true,
+        }
+    }
+}
+
+/// A single character on the screen along with the formats it was written with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub formats: Vec<Fromatting>,
+}
+
+impl Cell {
+    fn blank() -> Cell {
+        Cell {
+            c: ' ',
+            formats: Vec::new(),
+        }
+    }
+
+    fn is_blank(&self) -> bool {
+        self.c == ' ' && self.formats.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Line {
+    cells: Vec<Cell>,
+    /// The text on this line carries on to the next line as it was too long to fit
+    wrapped: bool,
+}
+
+impl Line {
+    fn new(columns: usize) -> Line {
+        Line {
+            cells: vec![Cell::blank(); columns],
+            wrapped: false,
+        }
+    }
+
+    fn clear(&mut self, start: usize, end: usize) {
+        let end = end.min(self.cells.len());
+        if start < end {
+            self.cells[start..end].fill(Cell::blank());
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Cursor {
+    row: usize,
+    /// This can be one past the last column which means the next character printed wraps on to the next line
+    column: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Screen {
+    lines: Vec<Line>,
+    cursor: Cursor,
+    saved: Option<(Cursor, Vec<Fromatting>)>,
+    /// The first line of the scrolling region
+    top: usize,
+    /// The last line of the scrolling region
+    bottom: usize,
+}
+
+impl Screen {
+    fn new(rows: usize, columns: usize) -> Screen {
+        Screen {
+            lines: vec![Line::new(columns); rows],
+            cursor: Cursor::default(),
+            saved: None,
+            top: 0,
+            bottom: rows - 1,
+        }
+    }
+
+    /// Moves the lines in the scrolling region up, lines that leave the top of the screen are added to the
+    /// scrollback if one is given.
+    fn scroll_up(&mut self, n: usize, columns: usize, mut scrollback: Option<&mut VecDeque<Line>>) {
+        for _ in 0..n.min(self.bottom - self.top + 1) {
+            let line = self.lines.remove(self.top);
+            if self.top == 0 {
+                if let Some(scrollback) = scrollback.as_mut() {
+                    scrollback.push_back(line);
+                }
+            }
+            self.lines.insert(self.bottom, Line::new(columns));
+        }
+    }
+
+    fn scroll_down(&mut self, n: usize, columns: usize) {
+        for _ in 0..n.min(self.bottom - self.top + 1) {
+            self.lines.remove(self.bottom);
+            self.lines.insert(self.top, Line::new(columns));
+        }
+    }
+}
+
+/// A virtual terminal that ansi::Text can be written to so that we can output what a person would have seen.
+///
+/// This keeps track of a grid of cells along with the cursor, scrolling region, saved cursor, alternate screen and
+/// the lines that have scrolled off the top of the screen.
+#[derive(Clone, Debug)]
+pub struct Terminal {
+    emulation: Emulation,
+    rows: usize,
+    columns: usize,
+    primary: Screen,
+    alternate: Option<Screen>,
+    scrollback: VecDeque<Line>,
+    /// The most lines the scrollback holds before the oldest are dropped
+    scrollback_lines: usize,
+    formats: Vec<Fromatting>,
+}
+
+impl Terminal {
+    pub fn new(rows: usize, columns: usize, emulation: Emulation) -> Terminal {
+        let rows = rows.max(1);
+        let columns = columns.max(1);
+        Terminal {
+            emulation,
+            rows,
+            columns,
+            primary: Screen::new(rows, columns),
+            alternate: None,
+            scrollback: VecDeque::new(),
+            scrollback_lines: Options::default().scrollback_lines,
+            formats: Vec::new(),
+        }
+    }
+
+    /// Sets the most lines that are kept in the scrollback
+    pub fn with_scrollback_lines(mut self, scrollback_lines: usize) -> Terminal {
+        self.scrollback_lines = scrollback_lines;
+        self
+    }
+
+    /// Returns a new terminal if the options ask for the input to be emulated
+    pub fn from_options(options: &Options) -> Option<Terminal> {
+        options.terminal.map(|emulation| {
+            Terminal::new(options.rows, options.columns, emulation)
+                .with_scrollback_lines(options.scrollback_lines)
+        })
+    }
+
+    /// Writes the text given to the terminal, this can be called as many times as needed as the input arrives.
+    pub fn write(&mut self, text: ansi::Text) {
+        for element in text.text {
+            match element {
+                ansi::TextElement::Text(string) => {
+                    for c in string.chars() {
+                        self.put_char(c);
+                    }
+                }
+                ansi::TextElement::Marker(sequence) => self.escape_sequence(&sequence),
+            }
+        }
+    }
+
+    /// Returns what is shown by the terminal, the lines that scrolled off the top of the screen are included
+    /// when emulating the scrollback.
+    pub fn text(&self) -> internal_format::Text {
+        let screen = self.alternate.as_ref().unwrap_or(&self.primary);
+        let lines: Vec<&Line> = match self.emulation {
+            Emulation::Screen => screen.lines.iter().collect(),
+            Emulation::Scrollback => self.scrollback.iter().chain(screen.lines.iter()).collect(),
+        };
+        render(&lines)
+    }
+
+    fn screen(&mut self) -> &mut Screen {
+        match self.alternate.as_mut() {
+            Some(screen) => screen,
+            None => &mut self.primary,
+        }
+    }
+
+    fn cursor(&mut self) -> &mut Cursor {
+        &mut self.screen().cursor
+    }
+
+    fn line(&mut self) -> &mut Line {
+        let screen = self.screen();
+        &mut screen.lines[screen.cursor.row]
+    }
+
+    fn put_char(&mut self, c: char) {
+        let columns = self.columns;
+        match c {
+            // a tty normally turns a new line into a carriage return followed by a line feed
+            '\n' | '\x0b' | '\x0c' => {
+                self.cursor().column = 0;
+                self.line_feed();
+            }
+            '\r' => self.cursor().column = 0,
+            '\x08' => {
+                let cursor = self.cursor();
+                cursor.column = cursor.column.min(columns - 1).saturating_sub(1);
+            }
+            '\t' => {
+                let cursor = self.cursor();
+                cursor.column = ((cursor.column / 8 + 1) * 8).min(columns - 1);
+            }
+            '\x00'..='\x1f' | '\x7f' => {}
+            _ => self.print(c),
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        if self.cursor().column >= self.columns {
+            self.line().wrapped = true;
+            self.cursor().column = 0;
+            self.line_feed();
+        }
+        let cell = Cell {
+            c,
+            formats: self.formats.clone(),
+        };
+        let column = self.cursor().column;
+        self.line().cells[column] = cell;
+        self.cursor().column += 1;
+    }
+
+    fn line_feed(&mut self) {
+        let screen = self.screen();
+        if screen.cursor.row == screen.bottom {
+            self.scroll_up(1);
+        } else if screen.cursor.row + 1 < screen.lines.len() {
+            screen.cursor.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        let columns = self.columns;
+        let screen = self.screen();
+        if screen.cursor.row == screen.top {
+            screen.scroll_down(1, columns);
+        } else {
+            screen.cursor.row = screen.cursor.row.saturating_sub(1);
+        }
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        // the lines that scroll off are only needed when they are going to be output
+        let scrollback = match self.emulation {
+            Emulation::Screen => None,
+            Emulation::Scrollback => Some(&mut self.scrollback),
+        };
+        match self.alternate.as_mut() {
+            Some(screen) => screen.scroll_up(n, self.columns, None),
+            None => self.primary.scroll_up(n, self.columns, scrollback),
+        }
+        let excess = self.scrollback.len().saturating_sub(self.scrollback_lines);
+        self.scrollback.drain(..excess);
+    }
+
+    fn save_cursor(&mut self) {
+        let formats = self.formats.clone();
+        let screen = self.screen();
+        screen.saved = Some((screen.cursor, formats));
+    }
+
+    fn restore_cursor(&mut self) {
+        let (cursor, formats) = self.screen().saved.clone().unwrap_or_default();
+        self.screen().cursor = cursor;
+        self.formats = formats;
+    }
+
+    fn move_to(&mut self, row: u16, column: u16) {
+        let (rows, columns) = (self.rows, self.columns);
+        let cursor = self.cursor();
+        cursor.row = (usize::from(row.max(1)) - 1).min(rows - 1);
+        cursor.column = (usize::from(column.max(1)) - 1).min(columns - 1);
+    }
+
+    fn escape_sequence(&mut self, sequence: &ansi::EscapeSequence) {
+        match sequence {
+            ansi::EscapeSequence::Fe(
+                fe @ ansi::FeEscapeSequence::ControlSequence(control_sequence),
+            ) => {
+                self.control_sequence(control_sequence);
+                self.apply_formats(fe);
+            }
+            ansi::EscapeSequence::Fe(ansi::FeEscapeSequence::Index) => self.line_feed(),
+            ansi::EscapeSequence::Fe(ansi::FeEscapeSequence::NextLine) => {
+                self.cursor().column = 0;
+                self.line_feed();
+            }
+            ansi::EscapeSequence::Fe(ansi::FeEscapeSequence::ReverseIndex) => self.reverse_index(),
+            ansi::EscapeSequence::Fe(fe) => self.apply_formats(fe),
+            ansi::EscapeSequence::Fp(ansi::FpEscapeSequence::SaveCursor) => self.save_cursor(),
+            ansi::EscapeSequence::Fp(ansi::FpEscapeSequence::RestoreCursor) => {
+                self.restore_cursor()
+            }
+            ansi::EscapeSequence::Fs(ansi::FsEscapeSequence::ResetToInitialState) => {
+                *self = Terminal::new(self.rows, self.columns, self.emulation)
+                    .with_scrollback_lines(self.scrollback_lines)
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_formats(&mut self, fe: &ansi::FeEscapeSequence) {
+        if let Some(toggles) = Fromatting::from_ansi(fe) {
+            for toggle in toggles.iter() {
+                internal_format::apply(&mut self.formats, toggle);
+            }
+        }
+    }
+
+    fn control_sequence(&mut self, control_sequence: &ansi::ControlSequence) {
+        let (rows, columns) = (self.rows, self.columns);
+        let count = |n: &u16| usize::from(*n.max(&1));
+        match control_sequence {
+            ansi::ControlSequence::CursorUp(n) => {
+                let screen = self.screen();
+                let top = if screen.cursor.row >= screen.top {
+                    screen.top
+                } else {
+                    0
+                };
+                screen.cursor.row = screen.cursor.row.saturating_sub(count(n)).max(top);
+            }
+            ansi::ControlSequence::CursorDown(n) => {
+                let screen = self.screen();
+                let bottom = if screen.cursor.row <= screen.bottom {
+                    screen.bottom
+                } else {
+                    rows - 1
+                };
+                screen.cursor.row = (screen.cursor.row + count(n)).min(bottom);
+            }
+            ansi::ControlSequence::CursorForward(n) => {
+                let cursor = self.cursor();
+                cursor.column = (cursor.column + count(n)).min(columns - 1);
+            }
+            ansi::ControlSequence::CursorBack(n) => {
+                let cursor = self.cursor();
+                cursor.column = cursor.column.min(columns - 1).saturating_sub(count(n));
+            }
+            ansi::ControlSequence::CursorNextLine(n) => {
+                let cursor = self.cursor();
+                cursor.row = (cursor.row + count(n)).min(rows - 1);
+                cursor.column = 0;
+            }
+            ansi::ControlSequence::CursorPreviousLine(n) => {
+                let cursor = self.cursor();
+                cursor.row = cursor.row.saturating_sub(count(n));
+                cursor.column = 0;
+            }
+            ansi::ControlSequence::CursorHorizontalAbsolute(column) => {
+                let row = self.cursor().row as u16 + 1;
+                self.move_to(row, *column);
+            }
+            ansi::ControlSequence::VerticalPositionAbsolute(row) => {
+                let column = self.cursor().column.min(columns - 1) as u16 + 1;
+                self.move_to(*row, column);
+            }
+            ansi::ControlSequence::CursorPosition(row, column)
+            | ansi::ControlSequence::HorizonalVerticalPosition(row, column) => {
+                self.move_to(*row, *column)
+            }
+            ansi::ControlSequence::EraseInLine(n) => {
+                let column = self.cursor().column;
+                let line = self.line();
+                match n {
+                    0 => {
+                        line.clear(column, columns);
+                        line.wrapped = false;
+                    }
+                    1 => line.clear(0, column + 1),
+                    2 => {
+                        line.clear(0, columns);
+                        line.wrapped = false;
+                    }
+                    _ => {}
+                }
+            }
+            ansi::ControlSequence::EraseInDisplay(n) => {
+                let cursor = self.screen().cursor;
+                let screen = self.screen();
+                let (start, end) = match n {
+                    0 => {
+                        screen.lines[cursor.row].clear(cursor.column, columns);
+                        (cursor.row + 1, rows)
+                    }
+                    1 => {
+                        screen.lines[cursor.row].clear(0, cursor.column + 1);
+                        (0, cursor.row)
+                    }
+                    2 => (0, rows),
+                    _ => (0, 0),
+                };
+                for line in screen.lines[start..end].iter_mut() {
+                    *line = Line::new(columns);
+                }
+                // 3 only clears the lines that have scrolled off of the screen
+                if *n == 3 {
+                    self.scrollback.clear();
+                }
+            }
+            ansi::ControlSequence::EraseCharacters(n) => {
+                let column = self.cursor().column;
+                self.line().clear(column, column + count(n));
+            }
+            ansi::ControlSequence::InsertCharacters(n) => {
+                let column = self.cursor().column.min(columns - 1);
+                let cells = &mut self.line().cells;
+                for _ in 0..count(n).min(columns - column) {
+                    cells.insert(column, Cell::blank());
+                }
+                cells.truncate(columns);
+            }
+            ansi::ControlSequence::DeleteCharacters(n) => {
+                let column = self.cursor().column.min(columns - 1);
+                let cells = &mut self.line().cells;
+                for _ in 0..count(n).min(columns - column) {
+                    cells.remove(column);
+                    cells.push(Cell::blank());
+                }
+            }
+            ansi::ControlSequence::InsertLines(n) => {
+                let screen = self.screen();
+                let row = screen.cursor.row;
+                if (screen.top..=screen.bottom).contains(&row) {
+                    for _ in 0..count(n).min(screen.bottom - row + 1) {
+                        screen.lines.remove(screen.bottom);
+                        screen.lines.insert(row, Line::new(columns));
+                    }
+                    screen.cursor.column = 0;
+                }
+            }
+            ansi::ControlSequence::DeleteLines(n) => {
+                let screen = self.screen();
+                let row = screen.cursor.row;
+                if (screen.top..=screen.bottom).contains(&row) {
+                    for _ in 0..count(n).min(screen.bottom - row + 1) {
+                        screen.lines.remove(row);
+                        screen.lines.insert(screen.bottom, Line::new(columns));
+                    }
+                    screen.cursor.column = 0;
+                }
+            }
+            ansi::ControlSequence::ScrollUp(n) => self.scroll_up(count(n)),
+            ansi::ControlSequence::ScrollDown(n) => self.screen().scroll_down(count(n), columns),
+            ansi::ControlSequence::SetScrollingRegion(top, bottom) => {
+                let top = usize::from(*top.max(&1)) - 1;
+                let bottom = match bottom {
+                    0 => rows - 1,
+                    _ => (usize::from(*bottom) - 1).min(rows - 1),
+                };
+                if top < bottom {
+                    let screen = self.screen();
+                    screen.top = top;
+                    screen.bottom = bottom;
+                    screen.cursor = Cursor::default();
+                }
+            }
+            ansi::ControlSequence::SaveCursorPosistion => self.save_cursor(),
+            ansi::ControlSequence::RestoreCursorPosistion => self.restore_cursor(),
+            ansi::ControlSequence::SetMode(modes) => {
+                for mode in modes.dec_private_modes() {
+                    self.set_mode(mode, true);
+                }
+            }
+            ansi::ControlSequence::ResetMode(modes) => {
+                for mode in modes.dec_private_modes() {
+                    self.set_mode(mode, false);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set_mode(&mut self, mode: ansi::DecPrivateMode, enabled: bool) {
+        match mode {
+            ansi::DecPrivateMode::AltScreen
+            | ansi::DecPrivateMode::AltScreenClear
+            | ansi::DecPrivateMode::AltScreenSaveCursor => {
+                let save_cursor = mode == ansi::DecPrivateMode::AltScreenSaveCursor;
+                if enabled && self.alternate.is_none() {
+                    if save_cursor {
+                        self.save_cursor();
+                    }
+                    let mut screen = Screen::new(self.rows, self.columns);
+                    screen.cursor = self.primary.cursor;
+                    self.alternate = Some(screen);
+                } else if !enabled && self.alternate.is_some() {
+                    self.alternate = None;
+                    if save_cursor {
+                        self.restore_cursor();
+                    }
+                }
+            }
+            ansi::DecPrivateMode::SaveCursor => match enabled {
+                true => self.save_cursor(),
+                false => self.restore_cursor(),
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Applies the movement within a line, a carriage return goes back to the start of the line, a backspace goes
+/// back a single cell and erase in line clears the line. Anything written afterwards overwrites what was there.
+///
+/// Each line is output once it has been ended with a new line so only what was left on it is kept, the formats
+/// that text was written with are kept for each cell.
+#[derive(Clone, Debug, Default)]
+pub struct LineDiscipline {
+    cells: Vec<Cell>,
+    column: usize,
+    formats: Vec<Fromatting>,
+}
+
+impl LineDiscipline {
+    pub fn new() -> LineDiscipline {
+        LineDiscipline {
+            cells: Vec::new(),
+            column: 0,
+            formats: Vec::new(),
+        }
+    }
+
+    /// Converts the text given returning every line that has been ended, the current line is held on to until
+    /// it is ended or finish is called.
+    pub fn convert(&mut self, text: ansi::Text) -> internal_format::Text {
+        let mut renderer = Renderer::new();
+        for element in text.text {
+            match element {
+                ansi::TextElement::Text(string) => {
+                    for c in string.chars() {
+                        self.put_char(c, &mut renderer);
+                    }
+                }
+                ansi::TextElement::Marker(ansi::EscapeSequence::Fe(fe)) => {
+                    self.escape_sequence(&fe)
+                }
+                ansi::TextElement::Marker(_) => {}
+            }
+        }
+        renderer.finish()
+    }
+
+    /// Returns whatever is left on the current line once there is no more input
+    pub fn finish(&mut self) -> internal_format::Text {
+        let mut renderer = Renderer::new();
+        renderer.cells(&self.cells);
+        *self = LineDiscipline {
+            formats: std::mem::take(&mut self.formats),
+            ..LineDiscipline::new()
+        };
+        renderer.finish()
+    }
+
+    fn put_char(&mut self, c: char, renderer: &mut Renderer) {
+        match c {
+            '\n' => {
+                renderer.cells(&self.cells);
+                renderer.end_line();
+                self.cells.clear();
+                self.column = 0;
+            }
+            '\r' => self.column = 0,
+            '\x08' => self.column = self.column.saturating_sub(1),
+            _ => {
+                let cell = Cell {
+                    c,
+                    formats: self.formats.clone(),
+                };
+                match self.cells.get_mut(self.column) {
+                    Some(current) => *current = cell,
+                    None => {
+                        self.cells.resize(self.column, Cell::blank());
+                        self.cells.push(cell);
+                    }
+                }
+                self.column += 1;
+            }
+        }
+    }
+
+    fn escape_sequence(&mut self, fe: &ansi::FeEscapeSequence) {
+        let count = |n: &u16| usize::from(*n.max(&1));
+        match fe {
+            ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::EraseInLine(n)) => {
+                match n {
+                    0 => self.cells.truncate(self.column),
+                    1 => {
+                        let end = (self.column + 1).min(self.cells.len());
+                        self.cells[..end].fill(Cell::blank());
+                    }
+                    2 => self.cells.clear(),
+                    _ => {}
+                }
+            }
+            ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::CursorBack(n)) => {
+                self.column = self.column.saturating_sub(count(n))
+            }
+            ansi::FeEscapeSequence::ControlSequence(ansi::ControlSequence::CursorForward(n)) => {
+                self.column += count(n)
+            }
+            ansi::FeEscapeSequence::ControlSequence(
+                ansi::ControlSequence::CursorHorizontalAbsolute(n),
+            ) => self.column = count(n) - 1,
+            _ => {
+                if let Some(toggles) = Fromatting::from_ansi(fe) {
+                    for toggle in toggles.iter() {
+                        internal_format::apply(&mut self.formats, toggle);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Turns the parsed input into the text that is output, depending on the options this is converted as it is,
+/// run through a line discipline or run through a virtual terminal. Overstrike is applied before any of these and
+/// the rendering quirks are applied after.
+#[derive(Debug)]
+pub struct Processor {
+    overstrike: Option<Overstrike>,
+    stage: Stage,
+    quirks: Option<internal_format::Quirks>,
+}
+
+#[derive(Debug)]
+enum Stage {
+    Convert(internal_format::Converter),
+    LineDiscipline(LineDiscipline),
+    Terminal(Box<Terminal>),
+}
+
+impl Processor {
+    pub fn new(options: &Options) -> Processor {
+        Processor {
+            overstrike: match options.overstrike {
+                true => Some(Overstrike::new()),
+                false => None,
+            },
+            stage: match Terminal::from_options(options) {
+                Some(terminal) => Stage::Terminal(Box::new(terminal)),
+                None if options.line_discipline => Stage::LineDiscipline(LineDiscipline::new()),
+                None => Stage::Convert(internal_format::Converter::new()),
+            },
+            quirks: None,
+        }
+    }
+
+    /// Shows the formats the way a terminal would using the palette given, nothing is changed unless one of the
+    /// options is set.
+    pub fn with_quirks(
+        mut self,
+        options: &internal_format::Options,
+        palette: Palette,
+    ) -> Processor {
+        self.quirks = options
+            .any()
+            .then(|| internal_format::Quirks::new(options, palette));
+        self
+    }
+
+    /// Processes the next part of the input returning the text that is ready to be output
+    pub fn process(&mut self, text: ansi::Text) -> internal_format::Text {
+        let text = match &mut self.overstrike {
+            Some(overstrike) => overstrike.convert(text),
+            None => text,
+        };
+        let text = match &mut self.stage {
+            Stage::Convert(converter) => converter.convert(text),
+            Stage::LineDiscipline(line_discipline) => line_discipline.convert(text),
+            Stage::Terminal(terminal) => {
+                terminal.write(text);
+                internal_format::Text { text: Vec::new() }
+            }
+        };
+        self.apply_quirks(text)
+    }
+
+    /// Processes the last part of the input returning everything that is left to be output
+    pub fn finish(&mut self, text: ansi::Text) -> internal_format::Text {
+        let text = match &mut self.overstrike {
+            Some(overstrike) => {
+                let mut result = overstrike.convert(text);
+                result.text.append(&mut overstrike.finish().text);
+                result
+            }
+            None => text,
+        };
+        let text = match &mut self.stage {
+            Stage::Convert(converter) => converter.convert(text),
+            Stage::LineDiscipline(line_discipline) => {
+                let mut result = line_discipline.convert(text);
+                result.text.append(&mut line_discipline.finish().text);
+                result
+            }
+            Stage::Terminal(terminal) => {
+                terminal.write(text);
+                terminal.text()
+            }
+        };
+        self.apply_quirks(text)
+    }
+
+    fn apply_quirks(&mut self, text: internal_format::Text) -> internal_format::Text {
+        match &mut self.quirks {
+            Some(quirks) => quirks.convert(text),
+            None => text,
+        }
+    }
+}
+
+/// Converts the lines given into Text, blank lines at the end and blank cells at the end of each line are dropped
+fn render(lines: &[&Line]) -> internal_format::Text {
+    let mut renderer = Renderer::new();
+    let last_line = lines
+        .iter()
+        .rposition(|line| line.wrapped || line.cells.iter().any(|cell| !cell.is_blank()));
+    for line in lines
+        .iter()
+        .take(last_line.map_or(0, |last_line| last_line + 1))
+    {
+        let cells = match line.wrapped {
+            true => &line.cells[..],
+            false => {
+                let end = line
+                    .cells
+                    .iter()
+                    .rposition(|cell| !cell.is_blank())
+                    .map_or(0, |end| end + 1);
+                &line.cells[..end]
+            }
+        };
+        renderer.cells(cells);
+        if !line.wrapped {
+            renderer.end_line();
+        }
+    }
+    renderer.finish()
+}
+
+/// Builds up Text from cells adding markers wherever the formats change from one cell to the next
+struct Renderer {
+    text: internal_format::Text,
+    active: Vec<Fromatting>,
+    buffer: String,
+}
+
+impl Renderer {
+    fn new() -> Renderer {
+        Renderer {
+            text: internal_format::Text { text: Vec::new() },
+            active: Vec::new(),
+            buffer: String::new(),
+        }
+    }
+
+    fn cells(&mut self, cells: &[Cell]) {
+        for cell in cells {
+            if cell.formats != self.active {
+                self.change_formats(&cell.formats);
+            }
+            self.buffer.push(cell.c);
+        }
+    }
+
+    fn end_line(&mut self) {
+        // the formats are not carried over the end of the line
+        if !self.active.is_empty() {
+            self.change_formats(&[]);
+        }
+        self.buffer.push('\n');
+    }
+
+    fn finish(mut self) -> internal_format::Text {
+        self.change_formats(&[]);
+        self.text
+    }
+
+    /// Adds the markers needed to go from the active formats to the formats given
+    fn change_formats(&mut self, formats: &[Fromatting]) {
+        if !self.buffer.is_empty() {
+            self.text
+                .text
+                .push(internal_format::TextElement::Text(std::mem::take(
+                    &mut self.buffer,
+                )));
+        }
+        for fmt in self.active.iter().rev() {
+            if !formats.contains(fmt) {
+                self.text
+                    .text
+                    .push(internal_format::TextElement::Marker(common::Toggle::UnSet(
+                        fmt.clone(),
+                    )));
+            }
+        }
+        for fmt in formats.iter() {
+            if !self.active.contains(fmt) {
+                self.text
+                    .text
+                    .push(internal_format::TextElement::Marker(common::Toggle::Set(
+                        fmt.clone(),
+                    )));
+            }
+        }
+        self.active = formats.to_vec();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Emulation, LineDiscipline, Options, Processor, Terminal};
+    use crate::{
+        common, input_fmt::ansi, internal_format, output_fmt::text::TextWriter,
+        output_fmt::Formatter,
+    };
+
+    fn emulate(input: &str, rows: usize, columns: usize, emulation: Emulation) -> String {
+        let mut terminal = Terminal::new(rows, columns, emulation);
+        terminal.write(ansi::Text::from(input.to_string()));
+        TextWriter::new().render(&terminal.text()).unwrap()
+    }
+
+    #[test]
+    fn terminal_screen() {
+        for (input, expected_result) in [
+            ("", ""),
+            ("hello\n", "hello\n"),
+            ("10%\r50%\r100%\n", "100%\n"),
+            ("hello\x1b[1;1Hj", "jello\n"),
+            ("abcdef\r\x1b[3C\x1b[K", "abc\n"),
+            ("abcdef\x1b[2G\x1b[1K", "  cdef\n"),
+            ("one\ntwo\nthree\x1b[2J\x1b[Hfour", "four\n"),
+            ("a\x1b[3;5Hb", "a\n\n    b\n"),
+            ("abc\x1b[2D\x1b[1Pz", "az\n"),
+            ("abc\x1b[2D\x1b[1@z", "azbc\n"),
+            ("abcdef\x1b[1G\x1b[3X", "   def\n"),
+            ("first\x1b7\nsecond\x1b8!", "first!\nsecond\n"),
+            (
+                "before\n\x1b[?1049hcurses ui\x1b[?1049lafter\n",
+                "before\nafter\n",
+            ),
+            ("old\x1bcnew", "new\n"),
+            ("a\tb", "a       b\n"),
+        ] {
+            assert_eq!(
+                emulate(input, 4, 10, Emulation::Screen),
+                expected_result,
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn terminal_scrollback() {
+        let input = "1\n2\n3\n4\n5";
+        assert_eq!(emulate(input, 3, 10, Emulation::Screen), "3\n4\n5\n");
+        assert_eq!(
+            emulate(input, 3, 10, Emulation::Scrollback),
+            "1\n2\n3\n4\n5\n"
+        );
+        assert_eq!(
+            emulate("abcdefghij\nk", 3, 4, Emulation::Scrollback),
+            "abcdefghij\nk\n"
+        );
+        assert_eq!(
+            emulate("1\n2\n3\x1b[3J\n4", 2, 10, Emulation::Scrollback),
+            "2\n3\n4\n"
+        );
+        // the screen never shows the lines that scrolled off so they aren't kept
+        let mut terminal = Terminal::new(2, 10, Emulation::Screen);
+        terminal.write(ansi::Text::from("1\n2\n3\n4".to_string()));
+        assert!(terminal.scrollback.is_empty());
+        let mut terminal = Terminal::new(2, 10, Emulation::Scrollback).with_scrollback_lines(2);
+        terminal.write(ansi::Text::from("1\n2\n3\n4\n5\n6".to_string()));
+        assert_eq!(
+            TextWriter::new().render(&terminal.text()).unwrap(),
+            "3\n4\n5\n6\n"
+        );
+    }
+
+    #[test]
+    fn terminal_scrolling_region() {
+        // the header and footer stay put while the lines between them scroll
+        let input = "header\n\n\nfooter\x1b[2;3r\x1b[2Hone\ntwo\nthree";
+        assert_eq!(
+            emulate(input, 4, 10, Emulation::Screen),
+            "header\ntwo\nthree\nfooter\n"
+        );
+        assert_eq!(
+            emulate("a\nb\nc\x1b[1;1H\x1bMz", 3, 10, Emulation::Screen),
+            "z\na\nb\n"
+        );
+        assert_eq!(
+            emulate("a\nb\nc\x1b[2H\x1b[1L", 3, 10, Emulation::Screen),
+            "a\n\nb\n"
+        );
+        assert_eq!(
+            emulate("a\nb\nc\x1b[1H\x1b[1M", 3, 10, Emulation::Screen),
+            "b\nc\n"
+        );
+    }
+
+    #[test]
+    fn terminal_formats() {
+        let mut terminal = Terminal::new(2, 10, Emulation::Screen);
+        terminal.write(ansi::Text::from("\x1b[1mab\x1b[0mc\x1b[1Hz".to_string()));
+        assert_eq!(
+            terminal.text(),
+            internal_format::Text {
+                text: vec![
+                    internal_format::TextElement::Text("z".to_string()),
+                    internal_format::TextElement::Marker(common::Toggle::Set(
+                        internal_format::Fromatting::Bold
+                    )),
+                    internal_format::TextElement::Text("b".to_string()),
+                    internal_format::TextElement::Marker(common::Toggle::UnSet(
+                        internal_format::Fromatting::Bold
+                    )),
+                    internal_format::TextElement::Text("c\n".to_string()),
+                ]
+            }
+        );
+    }
+
+    fn line_discipline(chunks: &[&str]) -> String {
+        let mut line_discipline = LineDiscipline::new();
+        let mut result = String::new();
+        for chunk in chunks {
+            let text = line_discipline.convert(ansi::Text::from(chunk.to_string()));
+            result.push_str(&TextWriter::new().render(&text).unwrap());
+        }
+        result.push_str(&TextWriter::new().render(&line_discipline.finish()).unwrap());
+        result
+    }
+
+    #[test]
+    fn line_discipline_overwrites() {
+        for (input, expected_result) in [
+            ("", ""),
+            ("one\n\ntwo", "one\n\ntwo"),
+            ("crlf\r\n", "crlf\n"),
+            ("[=   ] 25%\r[==  ] 50%\r[====] 100%\n", "[====] 100%\n"),
+            ("long line\rshort\n", "shortline\n"),
+            ("long line\rshort\x1b[K\n", "short\n"),
+            ("working |\x08/\x08-\x08\\\x08done\n", "working done\n"),
+            ("\x1b[2K\r50%\x1b[2K\r100%", "100%"),
+            ("abc\x1b[1Gz\x1b[2Cy", "zbcy"),
+            ("ab\x1b[3Cc", "ab   c"),
+        ] {
+            assert_eq!(line_discipline(&[input]), expected_result, "{:?}", input);
+        }
+        assert_eq!(
+            line_discipline(&["first\n10%", "\r20%", "\r100%\n"]),
+            "first\n100%\n"
+        );
+    }
+
+    #[test]
+    fn line_discipline_keeps_formats() {
+        let mut line_discipline = LineDiscipline::new();
+        let text = line_discipline.convert(ansi::Text::from(
+            "\x1b[31mred\x1b[0m\r\x1b[1mb\x1b[0m\n".to_string(),
+        ));
+        assert_eq!(
+            text,
+            internal_format::Text {
+                text: vec![
+                    internal_format::TextElement::Marker(common::Toggle::Set(
+                        internal_format::Fromatting::Bold
+                    )),
+                    internal_format::TextElement::Text("b".to_string()),
+                    internal_format::TextElement::Marker(common::Toggle::UnSet(
+                        internal_format::Fromatting::Bold
+                    )),
+                    internal_format::TextElement::Marker(common::Toggle::Set(
+                        internal_format::Fromatting::ForgroundColor(Some(
+                            common::AnsiColor::Named(1)
+                        ))
+                    )),
+                    internal_format::TextElement::Text("ed".to_string()),
+                    internal_format::TextElement::Marker(common::Toggle::UnSet(
+                        internal_format::Fromatting::ForgroundColor(Some(
+                            common::AnsiColor::Named(1)
+                        ))
+                    )),
+                    internal_format::TextElement::Text("\n".to_string()),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn processor_overstrike() {
+        let mut processor = Processor::new(&Options {
+            line_discipline: true,
+            overstrike: true,
+            ..Options::default()
+        });
+        let text = processor.finish(ansi::Text::from(
+            "N\x08NA\x08AM\x08ME\x08E\n  _\x08l_\x08s(1)\rx\n".to_string(),
+        ));
+        assert_eq!(
+            text,
+            internal_format::Text {
+                text: vec![
+                    internal_format::TextElement::Marker(common::Toggle::Set(
+                        internal_format::Fromatting::Bold
+                    )),
+                    internal_format::TextElement::Text("NAME".to_string()),
+                    internal_format::TextElement::Marker(common::Toggle::UnSet(
+                        internal_format::Fromatting::Bold
+                    )),
+                    internal_format::TextElement::Text("\nx ".to_string()),
+                    internal_format::TextElement::Marker(common::Toggle::Set(
+                        internal_format::Fromatting::Underline(internal_format::Underline::Single)
+                    )),
+                    internal_format::TextElement::Text("ls".to_string()),
+                    internal_format::TextElement::Marker(common::Toggle::UnSet(
+                        internal_format::Fromatting::Underline(internal_format::Underline::Single)
+                    )),
+                    internal_format::TextElement::Text("(1)\n".to_string()),
+                ]
+            }
+        );
+    }
+}